@@ -0,0 +1,95 @@
+//! Day 19 load test: connects many websocket clients spread over rooms, has
+//! one client per room post tweets, and reports how fast they reach everyone.
+//!
+//! Start the server, then:
+//!
+//! ```text
+//! cargo run --release --example load_test -- [base_url] [connections] [rooms] [tweets]
+//! ```
+//!
+//! Defaults are `http://localhost:8000 2000 20 50`. Thousands of sockets may
+//! need a higher `ulimit -n` on both ends.
+
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures::{stream, SinkExt, StreamExt};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+/// Connections opened at the same time while setting up.
+const CONNECT_CONCURRENCY: usize = 100;
+
+/// Give up waiting for deliveries after this long.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().collect();
+    let base = args.get(1).cloned().unwrap_or_else(|| "http://localhost:8000".to_string());
+    let connections: usize = args.get(2).map(|n| n.parse()).transpose()?.unwrap_or(2000);
+    let rooms: usize = args.get(3).map(|n| n.parse()).transpose()?.unwrap_or(20).clamp(1, connections);
+    let tweets: usize = args.get(4).map(|n| n.parse()).transpose()?.unwrap_or(50);
+    let ws_base = base.replacen("http", "ws", 1);
+
+    let http = reqwest::Client::new();
+    http.post(format!("{base}/19/reset")).send().await?.error_for_status()?;
+
+    let started = Instant::now();
+    let sockets: Vec<_> = stream::iter(0..connections)
+        .map(|i| {
+            let url = format!("{ws_base}/19/ws/room/{}/user/user{i}", i % rooms);
+            async move { connect_async(url).await.map(|(socket, _)| socket) }
+        })
+        .buffered(CONNECT_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<_, _>>()?;
+    println!("connected {connections} clients to {rooms} rooms in {:?}", started.elapsed());
+
+    // Every connection sees every tweet of its room, its own included.
+    let expected = (connections * tweets) as u64;
+    let delivered = Arc::new(AtomicU64::new(0));
+    let mut senders = Vec::new();
+    let mut readers = Vec::new();
+    for (i, socket) in sockets.into_iter().enumerate() {
+        let (sink, mut source) = socket.split();
+        if i < rooms {
+            senders.push(sink);
+        }
+        let delivered = delivered.clone();
+        readers.push(tokio::spawn(async move {
+            let mut received = 0;
+            while received < tweets {
+                match source.next().await {
+                    Some(Ok(Message::Text(_))) => {
+                        received += 1;
+                        delivered.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Some(Ok(_)) => (),
+                    // Closed, possibly for being too slow.
+                    _ => break,
+                }
+            }
+        }));
+    }
+
+    let started = Instant::now();
+    for n in 0..tweets {
+        for sink in &mut senders {
+            sink.send(Message::Text(format!(r#"{{"message":"tweet {n}"}}"#))).await?;
+        }
+    }
+    let finished = tokio::time::timeout(TIMEOUT, futures::future::join_all(readers)).await.is_ok();
+    let elapsed = started.elapsed();
+
+    let delivered = delivered.load(Ordering::Relaxed);
+    let views: u64 = http.get(format!("{base}/19/views")).send().await?.text().await?.parse()?;
+    println!(
+        "delivered {delivered}/{expected} tweets in {elapsed:?} ({:.0}/s), server counted {views} views{}",
+        delivered as f64 / elapsed.as_secs_f64(),
+        if finished { "" } else { ", timed out" },
+    );
+    Ok(())
+}
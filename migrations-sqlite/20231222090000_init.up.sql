@@ -0,0 +1,25 @@
+-- Add up migration script here
+
+-- Same shape as the Postgres schema in `migrations/`. Cycles in the region
+-- hierarchy are rejected by the store, since SQLite triggers cannot recurse.
+CREATE TABLE IF NOT EXISTS regions (
+                        id INTEGER PRIMARY KEY,
+                        name VARCHAR(50) NOT NULL CHECK (length(name) <= 50),
+                        parent_id INTEGER REFERENCES regions (id),
+                        CONSTRAINT regions_not_own_parent CHECK (parent_id <> id)
+);
+
+CREATE INDEX IF NOT EXISTS regions_parent_id_idx ON regions (parent_id);
+
+-- Timestamps are RFC 3339 text in UTC with milliseconds, so they sort as strings.
+CREATE TABLE IF NOT EXISTS orders (
+                        id INTEGER PRIMARY KEY,
+                        region_id INTEGER NOT NULL,
+                        gift_name VARCHAR(50) NOT NULL CHECK (length(gift_name) <= 50),
+                        quantity INTEGER NOT NULL,
+                        created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+                        CONSTRAINT orders_quantity_non_negative CHECK (quantity >= 0)
+);
+
+CREATE INDEX IF NOT EXISTS orders_region_id_idx ON orders (region_id);
+CREATE INDEX IF NOT EXISTS orders_created_at_idx ON orders (created_at);
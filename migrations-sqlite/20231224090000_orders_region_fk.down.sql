@@ -0,0 +1,21 @@
+-- Add down migration script here
+
+CREATE TABLE orders_old (
+                        id INTEGER PRIMARY KEY,
+                        region_id INTEGER NOT NULL,
+                        gift_name VARCHAR(50) NOT NULL CHECK (length(gift_name) <= 50),
+                        quantity INTEGER NOT NULL,
+                        created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+                        CONSTRAINT orders_quantity_non_negative CHECK (quantity >= 0)
+);
+
+INSERT INTO orders_old (id, region_id, gift_name, quantity, created_at)
+SELECT id, region_id, gift_name, quantity, created_at FROM orders;
+
+DROP TABLE orders;
+ALTER TABLE orders_old RENAME TO orders;
+
+CREATE INDEX IF NOT EXISTS orders_region_id_idx ON orders (region_id);
+CREATE INDEX IF NOT EXISTS orders_created_at_idx ON orders (created_at);
+
+ALTER TABLE regions DROP COLUMN placeholder;
@@ -0,0 +1,29 @@
+-- Add up migration script here
+
+-- Orders now belong to a known region, with flagged placeholder regions for
+-- the ids Day 13 loads; see the Postgres migration of the same name. SQLite
+-- cannot add a foreign key in place, so the orders table is rebuilt.
+ALTER TABLE regions ADD COLUMN placeholder BOOLEAN NOT NULL DEFAULT 0;
+
+INSERT INTO regions (id, name, placeholder)
+SELECT DISTINCT region_id, 'Region ' || region_id, 1
+FROM orders
+WHERE region_id NOT IN (SELECT id FROM regions);
+
+CREATE TABLE orders_new (
+                        id INTEGER PRIMARY KEY,
+                        region_id INTEGER NOT NULL REFERENCES regions (id),
+                        gift_name VARCHAR(50) NOT NULL CHECK (length(gift_name) <= 50),
+                        quantity INTEGER NOT NULL,
+                        created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+                        CONSTRAINT orders_quantity_non_negative CHECK (quantity >= 0)
+);
+
+INSERT INTO orders_new (id, region_id, gift_name, quantity, created_at)
+SELECT id, region_id, gift_name, quantity, created_at FROM orders;
+
+DROP TABLE orders;
+ALTER TABLE orders_new RENAME TO orders;
+
+CREATE INDEX IF NOT EXISTS orders_region_id_idx ON orders (region_id);
+CREATE INDEX IF NOT EXISTS orders_created_at_idx ON orders (created_at);
@@ -0,0 +1,11 @@
+-- Add down migration script here
+
+DROP INDEX IF EXISTS orders_region_id_idx;
+
+ALTER TABLE orders
+    DROP CONSTRAINT IF EXISTS orders_quantity_non_negative,
+    ALTER COLUMN region_id DROP NOT NULL,
+    ALTER COLUMN gift_name DROP NOT NULL,
+    ALTER COLUMN quantity DROP NOT NULL;
+
+DROP TABLE IF EXISTS regions;
@@ -0,0 +1,19 @@
+-- Add up migration script here
+
+-- Previously created at runtime by /18/reset.
+CREATE TABLE IF NOT EXISTS regions (
+                        id INT PRIMARY KEY,
+                        name VARCHAR(50)
+);
+
+ALTER TABLE regions
+    ALTER COLUMN name SET NOT NULL;
+
+-- No foreign key to regions: Day 13 loads orders without ever creating regions.
+ALTER TABLE orders
+    ALTER COLUMN region_id SET NOT NULL,
+    ALTER COLUMN gift_name SET NOT NULL,
+    ALTER COLUMN quantity SET NOT NULL,
+    ADD CONSTRAINT orders_quantity_non_negative CHECK (quantity >= 0);
+
+CREATE INDEX IF NOT EXISTS orders_region_id_idx ON orders (region_id);
@@ -0,0 +1,6 @@
+-- Add down migration script here
+
+DROP INDEX IF EXISTS orders_created_at_idx;
+
+ALTER TABLE orders
+    DROP COLUMN IF EXISTS created_at;
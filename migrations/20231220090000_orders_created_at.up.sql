@@ -0,0 +1,7 @@
+-- Add up migration script here
+
+-- Existing orders get the migration time; new ones default to their insert time.
+ALTER TABLE orders
+    ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE INDEX IF NOT EXISTS orders_created_at_idx ON orders (created_at);
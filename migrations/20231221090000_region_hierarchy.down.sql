@@ -0,0 +1,9 @@
+-- Add down migration script here
+
+DROP TRIGGER IF EXISTS regions_acyclic ON regions;
+DROP FUNCTION IF EXISTS regions_check_acyclic();
+DROP INDEX IF EXISTS regions_parent_id_idx;
+
+ALTER TABLE regions
+    DROP CONSTRAINT IF EXISTS regions_not_own_parent,
+    DROP COLUMN IF EXISTS parent_id;
@@ -0,0 +1,35 @@
+-- Add up migration script here
+
+ALTER TABLE regions
+    ADD COLUMN parent_id INT REFERENCES regions (id),
+    ADD CONSTRAINT regions_not_own_parent CHECK (parent_id <> id);
+
+CREATE INDEX IF NOT EXISTS regions_parent_id_idx ON regions (parent_id);
+
+-- Longer cycles cannot be expressed as a CHECK, so look for them after every
+-- statement that may have moved a region.
+CREATE FUNCTION regions_check_acyclic() RETURNS trigger AS $$
+DECLARE
+    looping INT;
+BEGIN
+    WITH RECURSIVE walk (id, ancestor) AS (
+        SELECT id, parent_id FROM regions WHERE parent_id IS NOT NULL
+        UNION
+        SELECT walk.id, regions.parent_id
+        FROM walk
+            JOIN regions ON regions.id = walk.ancestor
+        WHERE regions.parent_id IS NOT NULL
+    )
+    SELECT id INTO looping FROM walk WHERE ancestor = id LIMIT 1;
+
+    IF looping IS NOT NULL THEN
+        RAISE EXCEPTION 'region % is its own ancestor', looping
+            USING ERRCODE = 'check_violation';
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER regions_acyclic
+    AFTER INSERT OR UPDATE OF parent_id ON regions
+    FOR EACH STATEMENT EXECUTE FUNCTION regions_check_acyclic();
@@ -0,0 +1,12 @@
+-- Add up migration script here
+
+-- Day 19 chat history; ids are assigned by the server, increasing across rooms.
+CREATE TABLE IF NOT EXISTS chat_messages (
+    id BIGINT PRIMARY KEY,
+    room BIGINT NOT NULL,
+    username TEXT NOT NULL,
+    message TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS chat_messages_room_id_idx ON chat_messages (room, id);
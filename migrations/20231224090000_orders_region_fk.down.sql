@@ -0,0 +1,7 @@
+-- Add down migration script here
+
+ALTER TABLE orders
+    DROP CONSTRAINT IF EXISTS orders_region_id_fkey;
+
+ALTER TABLE regions
+    DROP COLUMN IF EXISTS placeholder;
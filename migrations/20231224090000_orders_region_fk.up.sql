@@ -0,0 +1,16 @@
+-- Add up migration script here
+
+-- Orders now belong to a known region. This replaces the "no foreign key"
+-- note in 20231219090000_regions_and_constraints: Day 13 still loads orders
+-- without regions, but creates a placeholder region for each unknown id.
+-- Placeholders are flagged, so a Day 13 reset can drop them with the orders.
+ALTER TABLE regions
+    ADD COLUMN placeholder BOOLEAN NOT NULL DEFAULT false;
+
+INSERT INTO regions (id, name, placeholder)
+SELECT DISTINCT region_id, 'Region ' || region_id, true
+FROM orders
+WHERE region_id NOT IN (SELECT id FROM regions);
+
+ALTER TABLE orders
+    ADD CONSTRAINT orders_region_id_fkey FOREIGN KEY (region_id) REFERENCES regions (id);
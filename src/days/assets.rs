@@ -0,0 +1,389 @@
+use std::{
+    io,
+    ops::Bound,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use axum::{
+    body::Body,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use axum_extra::TypedHeader;
+use chrono::{DateTime, Utc};
+use headers::{AcceptRanges, ContentLength, ContentRange, ContentType, ETag, IfModifiedSince, IfNoneMatch, LastModified, Range};
+use serde::{Deserialize, Serialize};
+
+use crate::days::imaging::{self, Operation, OutputFormat};
+
+/// Sidecar directory holding one JSON `AssetMeta` per stored asset.
+const META_DIR: &str = ".meta";
+/// Cache directory for generated thumbnail variants.
+const VARIANTS_DIR: &str = ".variants";
+const MAX_THUMBNAIL_DIMENSION: u32 = 1024;
+
+/// Metadata kept for every asset; `id` is the file name inside the store.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AssetMeta {
+    pub id: String,
+    pub original_name: Option<String>,
+    pub mime_type: String,
+    pub size: u64,
+    pub sha256: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub red_pixels: Option<u32>,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+/// Content-addressed files on disk. Uploads are stored as `<sha256>.<ext>`, files
+/// dropped into the directory by hand are served too, with metadata built on first access.
+#[derive(Clone, Debug)]
+pub struct AssetStore {
+    root: PathBuf,
+}
+
+impl AssetStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub async fn put(&self, original_name: Option<String>, data: &[u8]) -> io::Result<AssetMeta> {
+        let sha256 = sha256::digest(data);
+        let mime_type = detect_mime(original_name.as_deref(), data);
+        let extension = detect_extension(original_name.as_deref(), &mime_type)
+            .map(|ext| format!(".{ext}"))
+            .unwrap_or_default();
+        let id = format!("{sha256}{extension}");
+
+        if let Some(meta) = self.load_meta(&id).await? {
+            // Same content already stored.
+            return Ok(meta);
+        }
+
+        tokio::fs::create_dir_all(&self.root).await?;
+        tokio::fs::write(self.root.join(&id), data).await?;
+
+        let (meta, _) = build_meta(id, original_name, mime_type, sha256, data.to_vec(), Utc::now()).await?;
+        self.save_meta(&meta).await?;
+        Ok(meta)
+    }
+
+    pub async fn get(&self, id: &str) -> io::Result<Option<(AssetMeta, Vec<u8>)>> {
+        let Some(path) = self.asset_path(id) else {
+            return Ok(None);
+        };
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        match self.load_meta(id).await? {
+            Some(meta) => Ok(Some((meta, data))),
+            None => {
+                let modified = tokio::fs::metadata(&path).await?.modified()?;
+                let (meta, data) = build_meta(
+                    id.to_string(),
+                    Some(id.to_string()),
+                    detect_mime(Some(id), &data),
+                    sha256::digest(&data),
+                    data,
+                    modified.into(),
+                ).await?;
+                self.save_meta(&meta).await?;
+                Ok(Some((meta, data)))
+            }
+        }
+    }
+
+    pub async fn list(&self) -> io::Result<Vec<AssetMeta>> {
+        let mut ids = vec![];
+        let mut dir = match tokio::fs::read_dir(&self.root).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        while let Some(entry) = dir.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                ids.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        ids.sort();
+
+        let mut assets = vec![];
+        for id in ids {
+            if self.asset_path(&id).is_none() {
+                continue;
+            }
+            // Only files without a sidecar are read, to build one; the body is dropped right away.
+            let meta = match self.load_meta(&id).await? {
+                Some(meta) => Some(meta),
+                None => self.get(&id).await?.map(|(meta, _)| meta),
+            };
+            assets.extend(meta);
+        }
+        Ok(assets)
+    }
+
+    pub async fn delete(&self, id: &str) -> io::Result<bool> {
+        let Some(path) = self.asset_path(id) else {
+            return Ok(false);
+        };
+        match tokio::fs::remove_file(path).await {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        remove_if_exists(&self.meta_path(id)).await?;
+
+        let variants = self.root.join(VARIANTS_DIR);
+        if let Ok(mut dir) = tokio::fs::read_dir(&variants).await {
+            while let Some(entry) = dir.next_entry().await? {
+                if is_variant_of(&entry.file_name().to_string_lossy(), id) {
+                    remove_if_exists(&entry.path()).await?;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// A resized copy of an image asset, generated on first request and cached on disk.
+    pub async fn thumbnail(
+        &self,
+        id: &str,
+        width: u32,
+        height: u32,
+        format: OutputFormat,
+    ) -> Result<Option<(AssetMeta, Vec<u8>)>, (StatusCode, String)> {
+        let width = width.clamp(1, MAX_THUMBNAIL_DIMENSION);
+        let height = height.clamp(1, MAX_THUMBNAIL_DIMENSION);
+
+        let Some((meta, data)) = self.get(id).await.map_err(internal_error)? else {
+            return Ok(None);
+        };
+        let variant_id = variant_id(id, width, height, format);
+        let variant_path = self.root.join(VARIANTS_DIR).join(&variant_id);
+        let variant_meta = AssetMeta {
+            id: variant_id,
+            mime_type: format.mime_type().to_string(),
+            sha256: format!("{}-{width}x{height}-{}", meta.sha256, format.extension()),
+            ..meta
+        };
+
+        if let Ok(cached) = tokio::fs::read(&variant_path).await {
+            return Ok(Some((AssetMeta { size: cached.len() as u64, ..variant_meta }, cached)));
+        }
+
+        let resized = tokio::task::spawn_blocking(move || {
+            let (img, _) = imaging::decode(&data)?;
+            let img = imaging::transform(
+                img,
+                &[Operation::Resize { width, height, exact: false }],
+                MAX_THUMBNAIL_DIMENSION,
+            )?;
+            imaging::encode(&img, format, 85)
+        })
+            .await
+            .map_err(internal_error)?
+            .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+
+        tokio::fs::create_dir_all(self.root.join(VARIANTS_DIR)).await.map_err(internal_error)?;
+        tokio::fs::write(&variant_path, &resized).await.map_err(internal_error)?;
+
+        Ok(Some((AssetMeta { size: resized.len() as u64, ..variant_meta }, resized)))
+    }
+
+    /// Only plain file names are valid ids, so requests can't escape the store.
+    fn asset_path(&self, id: &str) -> Option<PathBuf> {
+        let valid = !id.is_empty()
+            && !id.starts_with('.')
+            && Path::new(id).file_name().map(|name| name == id).unwrap_or(false);
+        valid.then(|| self.root.join(id))
+    }
+
+    fn meta_path(&self, id: &str) -> PathBuf {
+        self.root.join(META_DIR).join(format!("{id}.json"))
+    }
+
+    async fn load_meta(&self, id: &str) -> io::Result<Option<AssetMeta>> {
+        match tokio::fs::read(self.meta_path(id)).await {
+            Ok(json) => Ok(serde_json::from_slice(&json).ok()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn save_meta(&self, meta: &AssetMeta) -> io::Result<()> {
+        tokio::fs::create_dir_all(self.root.join(META_DIR)).await?;
+        let json = serde_json::to_vec_pretty(meta).map_err(io::Error::other)?;
+        tokio::fs::write(self.meta_path(&meta.id), json).await
+    }
+}
+
+/// Images are decoded for their size and red pixels, so this runs on the
+/// blocking pool and hands `data` back afterwards.
+async fn build_meta(
+    id: String,
+    original_name: Option<String>,
+    mime_type: String,
+    sha256: String,
+    data: Vec<u8>,
+    uploaded_at: DateTime<Utc>,
+) -> io::Result<(AssetMeta, Vec<u8>)> {
+    tokio::task::spawn_blocking(move || {
+        let meta = describe(id, original_name, mime_type, sha256, &data, uploaded_at);
+        (meta, data)
+    })
+        .await
+        .map_err(io::Error::other)
+}
+
+fn describe(
+    id: String,
+    original_name: Option<String>,
+    mime_type: String,
+    sha256: String,
+    data: &[u8],
+    uploaded_at: DateTime<Utc>,
+) -> AssetMeta {
+    let img = mime_type.starts_with("image/")
+        .then(|| imaging::decode(data).ok())
+        .flatten();
+
+    AssetMeta {
+        id,
+        original_name,
+        mime_type,
+        size: data.len() as u64,
+        sha256,
+        width: img.as_ref().map(|(img, _)| img.width()),
+        height: img.as_ref().map(|(img, _)| img.height()),
+        red_pixels: img.as_ref().map(|(img, _)| imaging::count_red_pixels(img)),
+        uploaded_at,
+    }
+}
+
+fn variant_id(id: &str, width: u32, height: u32, format: OutputFormat) -> String {
+    format!("{id}-{width}x{height}.{}", format.extension())
+}
+
+/// Whether `name` is exactly `variant_id(id, ..)` for some size and format, so
+/// assets whose ids merely start with `{id}-` are left alone.
+fn is_variant_of(name: &str, id: &str) -> bool {
+    let Some((size, extension)) = name.strip_prefix(id)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .and_then(|rest| rest.split_once('.')) else {
+        return false;
+    };
+    let Some((width, height)) = size.split_once('x') else {
+        return false;
+    };
+    let number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let formats = [OutputFormat::Png, OutputFormat::Jpeg, OutputFormat::Webp];
+    number(width) && number(height) && formats.iter().any(|format| format.extension() == extension)
+}
+
+/// Sniff image content first, then fall back to the file name.
+fn detect_mime(name: Option<&str>, data: &[u8]) -> String {
+    if let Ok(format) = image::guess_format(data) {
+        return format.to_mime_type().to_string();
+    }
+    name.and_then(|name| mime_guess::from_path(name).first_raw())
+        .unwrap_or("application/octet-stream")
+        .to_string()
+}
+
+/// Keep the uploaded extension when it agrees with the content, otherwise derive one.
+fn detect_extension(name: Option<&str>, mime_type: &str) -> Option<String> {
+    name.and_then(|name| Path::new(name).extension())
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+        .filter(|ext| mime_guess::from_ext(ext).iter_raw().any(|m| m == mime_type))
+        .or_else(|| image::ImageFormat::from_mime_type(mime_type).map(|f| f.extensions_str()[0].to_string()))
+        .or_else(|| mime_guess::get_mime_extensions_str(mime_type)
+            .and_then(|exts| exts.first())
+            .map(|ext| ext.to_string()))
+}
+
+async fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+pub fn internal_error(e: impl ToString) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// Build a response honouring `If-None-Match`, `If-Modified-Since` and a single byte `Range`.
+pub fn conditional_response(
+    meta: &AssetMeta,
+    data: Vec<u8>,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
+    range: Option<TypedHeader<Range>>,
+) -> Response {
+    let etag = format!("\"{}\"", meta.sha256).parse::<ETag>().ok();
+    let modified = SystemTime::from(meta.uploaded_at);
+    let content_type = meta.mime_type.parse::<mime_guess::Mime>()
+        .unwrap_or(mime_guess::mime::APPLICATION_OCTET_STREAM);
+
+    let not_modified = match (&if_none_match, &etag) {
+        (Some(TypedHeader(inm)), Some(etag)) => !inm.precondition_passes(etag),
+        _ => if_modified_since
+            .map(|TypedHeader(ims)| !ims.is_modified(modified))
+            .unwrap_or(false),
+    };
+
+    let headers = (
+        etag.map(TypedHeader),
+        TypedHeader(LastModified::from(modified)),
+        TypedHeader(AcceptRanges::bytes()),
+    );
+
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    let len = data.len() as u64;
+    let Some(TypedHeader(range)) = range else {
+        return (headers, TypedHeader(ContentType::from(content_type)), data).into_response();
+    };
+
+    // Multiple ranges are not supported; serve the first one.
+    let bounds = range.satisfiable_ranges(len).next().and_then(|(start, end)| {
+        let start = match start {
+            Bound::Included(s) => s,
+            Bound::Excluded(s) => s + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match end {
+            Bound::Included(e) => e.min(len.saturating_sub(1)),
+            Bound::Excluded(e) => e.saturating_sub(1).min(len.saturating_sub(1)),
+            Bound::Unbounded => len.saturating_sub(1),
+        };
+        (start <= end && start < len).then_some((start, end))
+    });
+
+    match bounds {
+        Some((start, end)) => {
+            let body = data[start as usize..=end as usize].to_vec();
+            (
+                StatusCode::PARTIAL_CONTENT,
+                headers,
+                TypedHeader(ContentType::from(content_type)),
+                TypedHeader(ContentRange::bytes(start..=end, len).expect("valid range")),
+                TypedHeader(ContentLength(body.len() as u64)),
+                Body::from(body),
+            ).into_response()
+        }
+        None => (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            headers,
+            TypedHeader(ContentRange::unsatisfied_bytes(len)),
+            [(header::CONTENT_LENGTH, "0")],
+        ).into_response(),
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+use std::sync::OnceLock;
+use flate2::read::GzDecoder;
+
+/// Gzipped lists, one lowercase entry per line. Rebuild with `gzip -9 -n`.
+const COMMON_PASSWORDS: &[u8] = include_bytes!("../../data/common-passwords.txt.gz");
+const WORDS: &[u8] = include_bytes!("../../data/words.txt.gz");
+
+const FALSE_POSITIVE_RATE: f64 = 0.001;
+
+/// Keyboard rows walked left to right; walks the other way are checked reversed.
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+/// Set membership in a fixed bit array: no false negatives, and false
+/// positives at about the rate it was sized for.
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    len: u64,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    pub fn new(items: usize, false_positive_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let len = (-(items.max(1) as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let hashes = ((len as f64 / items.max(1) as f64) * ln2).round().max(1.0) as u32;
+        Self { bits: vec![0; len.div_ceil(64) as usize], len, hashes }
+    }
+
+    pub fn insert(&mut self, item: &str) {
+        for bit in self.positions(item) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        self.positions(item).all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Double hashing: the i-th position is `h1 + i * h2`.
+    fn positions(&self, item: &str) -> impl Iterator<Item = u64> {
+        let hash = |seed: u64| {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            item.hash(&mut hasher);
+            hasher.finish()
+        };
+        let (h1, h2) = (hash(0), hash(1) | 1);
+        let len = self.len;
+        (0..u64::from(self.hashes)).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % len)
+    }
+}
+
+/// The bundled lists, decompressed once.
+pub struct Corpus {
+    passwords: BloomFilter,
+    words: HashSet<String>,
+    /// Number of common passwords, for strength estimates.
+    pub size: usize,
+}
+
+fn lines(gzipped: &[u8]) -> Vec<String> {
+    let mut text = String::new();
+    GzDecoder::new(gzipped)
+        .read_to_string(&mut text)
+        .expect("bundled list is valid gzip");
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The corpus, loaded on first use; call it at startup to pay that cost early.
+pub fn corpus() -> &'static Corpus {
+    static CORPUS: OnceLock<Corpus> = OnceLock::new();
+    CORPUS.get_or_init(|| {
+        let common = lines(COMMON_PASSWORDS);
+        let mut passwords = BloomFilter::new(common.len(), FALSE_POSITIVE_RATE);
+        for password in &common {
+            passwords.insert(password);
+        }
+        Corpus {
+            passwords,
+            words: lines(WORDS).into_iter().collect(),
+            size: common.len(),
+        }
+    })
+}
+
+/// Lowercase with common letter substitutions undone, e.g. `P@ssw0rd` to `password`.
+fn unleet(input: &str) -> String {
+    input.chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            '0' => 'o',
+            '1' | '!' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' => 't',
+            c => c,
+        })
+        .collect()
+}
+
+impl Corpus {
+    /// On the common password list, as typed, with substitutions undone or
+    /// without trailing digits and symbols, like `P@ssw0rd1234`.
+    pub fn is_common(&self, input: &str) -> bool {
+        let listed = |candidate: &str| {
+            !candidate.is_empty() && (self.passwords.contains(candidate) || self.passwords.contains(&unleet(candidate)))
+        };
+        let lowercase = input.to_lowercase();
+        listed(&lowercase) || listed(lowercase.trim_end_matches(|c: char| !c.is_alphabetic()))
+    }
+
+    /// A single dictionary word once case, substitutions, digits and symbols
+    /// are ignored, like `Sunshine123!`.
+    pub fn is_dictionary_word(&self, input: &str) -> bool {
+        let letters = input.to_lowercase()
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .collect::<String>();
+        let unleet = unleet(input.trim_end_matches(|c: char| !c.is_alphanumeric()))
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .collect::<String>();
+        self.words.contains(&letters) || self.words.contains(&unleet)
+    }
+}
+
+/// The first run of at least `min_length` neighbouring keys on one keyboard
+/// row, in either direction, like `qwer` or `4321`.
+pub fn keyboard_walk(input: &str, min_length: usize) -> Option<String> {
+    let input = input.to_lowercase().chars().collect::<Vec<_>>();
+    let min_length = min_length.max(2);
+    let rows = KEYBOARD_ROWS.iter()
+        .flat_map(|row| [row.chars().collect::<String>(), row.chars().rev().collect()]);
+    for row in rows {
+        for window in input.windows(min_length) {
+            let walk = window.iter().collect::<String>();
+            if row.contains(&walk) {
+                return Some(walk);
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,166 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::{DateTime, SubsecRound, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tokio::sync::RwLock;
+use crate::days::password::Rule;
+
+/// Messages kept in memory per room, oldest dropped first.
+pub const ROOM_HISTORY: usize = 1000;
+
+/// Largest page served by the history endpoint.
+pub const MAX_PAGE: usize = 100;
+
+/// Bundled chat settings, see `ChatConfig::load`.
+const CONFIG: &str = include_str!("../../policies/chat.toml");
+
+/// Who moderates rooms and what messages are allowed.
+#[derive(Deserialize, Debug, Default)]
+pub struct ChatConfig {
+    /// Keys that allow moderating every room, passed as `?key=` on join.
+    #[serde(default)]
+    pub admin_keys: Vec<String>,
+    /// Content rules for tweets, direct messages and topics; `status` is unused.
+    #[serde(default)]
+    pub filters: Vec<Rule>,
+}
+
+impl ChatConfig {
+    /// The file named by `CHAT_CONFIG`, or `policies/chat.toml` as bundled.
+    pub fn load() -> Result<Self, String> {
+        let toml = match std::env::var("CHAT_CONFIG") {
+            Ok(path) => std::fs::read_to_string(&path).map_err(|e| format!("Cannot read {path}: {e}"))?,
+            Err(_) => CONFIG.to_string(),
+        };
+        toml::from_str(&toml).map_err(|e| format!("Invalid chat config: {e}"))
+    }
+
+    /// Reason of the first filter `message` fails.
+    pub fn rejects(&self, message: &str) -> Option<&str> {
+        self.filters
+            .iter()
+            .find(|rule| !rule.check.passes(message))
+            .map(|rule| rule.reason.as_str())
+    }
+}
+
+/// A chat message. Clients send only `message`; the server fills in the rest
+/// before broadcasting.
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct Tweet {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// Durable copy of the chat history, so it outlives a restart and goes back
+/// further than `ROOM_HISTORY`.
+#[async_trait]
+pub trait ChatArchive: Send + Sync {
+    /// Highest id stored so far, 0 when empty.
+    async fn last_id(&self) -> Result<i64, sqlx::Error>;
+
+    async fn append(&self, room: u32, tweet: &Tweet) -> Result<(), sqlx::Error>;
+
+    /// Up to `limit` messages of `room` older than `before`, oldest first.
+    async fn page(&self, room: u32, before: Option<i64>, limit: usize) -> Result<Vec<Tweet>, sqlx::Error>;
+}
+
+/// Recent messages of every room, plus the archive when one is configured.
+pub struct ChatHistory {
+    rooms: RwLock<HashMap<u32, VecDeque<Tweet>>>,
+    next_id: AtomicI64,
+    archive: Option<Arc<dyn ChatArchive>>,
+}
+
+impl ChatHistory {
+    /// Ids continue after the archived ones, so they stay unique across restarts.
+    pub async fn new(archive: Option<Arc<dyn ChatArchive>>) -> Result<Self, sqlx::Error> {
+        let last_id = match &archive {
+            Some(archive) => archive.last_id().await?,
+            None => 0,
+        };
+        Ok(Self {
+            rooms: RwLock::new(HashMap::new()),
+            next_id: AtomicI64::new(last_id + 1),
+            archive,
+        })
+    }
+
+    /// Stamp `tweet` with an id and the current time, remember it and hand it
+    /// to `publish`. Publishing happens under the history lock, so listeners
+    /// see tweets in id order. A failed archive write is logged and leaves the
+    /// in-memory history intact.
+    pub async fn record(&self, room: u32, mut tweet: Tweet, publish: impl FnOnce(&Tweet)) -> Tweet {
+        let mut rooms = self.rooms.write().await;
+        tweet.id = Some(self.next_id.fetch_add(1, Ordering::Relaxed));
+        // Microseconds, the precision Postgres keeps.
+        tweet.timestamp = Some(Utc::now().trunc_subsecs(6));
+
+        let history = rooms.entry(room).or_default();
+        if history.len() == ROOM_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(tweet.clone());
+        publish(&tweet);
+        drop(rooms);
+
+        if let Some(archive) = &self.archive {
+            if let Err(e) = archive.append(room, &tweet).await {
+                tracing::warn!("failed to archive message {:?}: {e}", tweet.id);
+            }
+        }
+        tweet
+    }
+
+    /// The last `count` messages of `room`, oldest first.
+    pub async fn recent(&self, room: u32, count: usize) -> Vec<Tweet> {
+        let rooms = self.rooms.read().await;
+        let Some(history) = rooms.get(&room) else {
+            return Vec::new();
+        };
+        history.iter().skip(history.len().saturating_sub(count)).cloned().collect()
+    }
+
+    /// Up to `limit` messages older than `before`, oldest first. Served from
+    /// memory when it holds a full page, otherwise from the archive merged
+    /// with memory: archive writes trail `record`, so the newest messages may
+    /// not have reached it yet.
+    pub async fn page(&self, room: u32, before: Option<i64>, limit: usize) -> Result<Vec<Tweet>, sqlx::Error> {
+        let limit = limit.min(MAX_PAGE);
+        let rooms = self.rooms.read().await;
+        let history = rooms.get(&room);
+        let older: Vec<&Tweet> = history
+            .map(|history| {
+                history
+                    .iter()
+                    .filter(|tweet| before.is_none_or(|before| tweet.id < Some(before)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        match &self.archive {
+            Some(archive) if older.len() < limit => {
+                let recent = older.into_iter().cloned().collect::<Vec<_>>();
+                drop(rooms);
+                let mut merged = archive.page(room, before, limit)
+                    .await?
+                    .into_iter()
+                    .chain(recent)
+                    .map(|tweet| (tweet.id, tweet))
+                    .collect::<BTreeMap<_, _>>()
+                    .into_values()
+                    .collect::<Vec<_>>();
+                Ok(merged.split_off(merged.len().saturating_sub(limit)))
+            }
+            _ => Ok(older[older.len().saturating_sub(limit)..].iter().map(|&tweet| tweet.clone()).collect()),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use crate::days::chat::{ChatArchive, Tweet};
+
+/// Chat history in the `chat_messages` table.
+#[derive(Clone)]
+pub struct PgChatArchive {
+    pool: PgPool,
+}
+
+impl PgChatArchive {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl ChatArchive for PgChatArchive {
+    async fn last_id(&self) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(r#"SELECT COALESCE(MAX(id), 0) AS "id!" FROM chat_messages"#)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn append(&self, room: u32, tweet: &Tweet) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT INTO chat_messages (id, room, username, message, created_at) VALUES ($1, $2, $3, $4, $5)",
+            tweet.id,
+            i64::from(room),
+            tweet.user,
+            tweet.message,
+            tweet.timestamp,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn page(&self, room: u32, before: Option<i64>, limit: usize) -> Result<Vec<Tweet>, sqlx::Error> {
+        let mut tweets = sqlx::query_as!(
+            Tweet,
+            r#"SELECT id AS "id?", username AS "user?", message, created_at AS "timestamp?"
+            FROM chat_messages
+            WHERE room = $1 AND ($2::BIGINT IS NULL OR id < $2)
+            ORDER BY id DESC
+            LIMIT $3"#,
+            i64::from(room),
+            before,
+            limit as i64,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        tweets.reverse();
+        Ok(tweets)
+    }
+}
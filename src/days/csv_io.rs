@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::io::Read;
+use axum::body::Body;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures::future::BoxFuture;
+use futures::TryStreamExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::io::{StreamReader, SyncIoBridge};
+use crate::days::orders::{self, InsertSummary, MissingRegions, OnConflict, Order, OrderStore, Region};
+
+/// Valid rows buffered before they are handed to the store.
+const ROWS_PER_CHUNK: usize = 1000;
+/// Errors listed in an import report; `rejected` still counts all of them.
+const MAX_REPORTED_ERRORS: usize = 100;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ExportParams {
+    #[serde(default)]
+    pub format: Format,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ImportParams {
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+    /// Reject the whole file if any row is invalid instead of skipping those rows.
+    #[serde(default)]
+    pub strict: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RowError {
+    pub line: u64,
+    pub error: String,
+}
+
+/// `received` counts every data row; it is split over `inserted`, `updated`,
+/// `skipped` (conflicts) and `rejected` (invalid rows).
+#[derive(Serialize, Debug, Default)]
+pub struct ImportReport {
+    #[serde(flatten)]
+    pub summary: InsertSummary,
+    pub rejected: usize,
+    pub errors: Vec<RowError>,
+}
+
+/// A table that can be bulk loaded from CSV. Rows are validated here and
+/// streamed to the store in batches, which writes them in one transaction.
+pub trait CsvRow: DeserializeOwned + Send + 'static {
+    /// Header columns every file must have.
+    const REQUIRED: &'static [&'static str];
+    /// Settings only this table has, e.g. what orders do about missing regions.
+    type Options: Send + 'static;
+
+    fn id(&self) -> i32;
+    fn validate(&self) -> Result<(), String>;
+    fn import(
+        store: &dyn OrderStore,
+        batches: mpsc::Receiver<Vec<Self>>,
+        on_conflict: OnConflict,
+        options: Self::Options,
+        proceed: oneshot::Receiver<bool>,
+    ) -> BoxFuture<'_, Result<Option<(usize, usize)>, sqlx::Error>>;
+}
+
+impl CsvRow for Order {
+    const REQUIRED: &'static [&'static str] = &["id", "region_id", "gift_name", "quantity"];
+    type Options = MissingRegions;
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        validate_name("gift_name", &self.gift_name)?;
+        if self.quantity < 0 {
+            return Err(format!("quantity {} is negative", self.quantity));
+        }
+        Ok(())
+    }
+
+    fn import(
+        store: &dyn OrderStore,
+        batches: mpsc::Receiver<Vec<Self>>,
+        on_conflict: OnConflict,
+        missing: MissingRegions,
+        proceed: oneshot::Receiver<bool>,
+    ) -> BoxFuture<'_, Result<Option<(usize, usize)>, sqlx::Error>> {
+        store.import_orders(batches, on_conflict, missing, proceed)
+    }
+}
+
+impl CsvRow for Region {
+    const REQUIRED: &'static [&'static str] = &["id", "name"];
+    type Options = ();
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        validate_name("name", &self.name)?;
+        if self.parent_id == Some(self.id) {
+            return Err(format!("region {} is its own parent", self.id));
+        }
+        Ok(())
+    }
+
+    fn import(
+        store: &dyn OrderStore,
+        batches: mpsc::Receiver<Vec<Self>>,
+        on_conflict: OnConflict,
+        _options: (),
+        proceed: oneshot::Receiver<bool>,
+    ) -> BoxFuture<'_, Result<Option<(usize, usize)>, sqlx::Error>> {
+        store.import_regions(batches, on_conflict, proceed)
+    }
+}
+
+fn validate_name(column: &str, name: &str) -> Result<(), String> {
+    match name.chars().count() {
+        0 => Err(format!("{column} is empty")),
+        len if len > 50 => Err(format!("{column} is longer than 50 characters")),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Default)]
+struct Parsed {
+    received: usize,
+    rejected: usize,
+    errors: Vec<RowError>,
+}
+
+impl Parsed {
+    fn reject(&mut self, line: u64, error: String) {
+        self.rejected += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(RowError { line, error });
+        }
+    }
+}
+
+/// Read CSV rows from `input`, sending valid ones in batches. Runs on a
+/// blocking thread; stops early if the receiver goes away.
+fn parse_rows<T: CsvRow>(input: impl Read, batches: mpsc::Sender<Vec<T>>) -> Result<Parsed, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input);
+
+    let headers = reader.headers().map_err(|e| format!("Invalid CSV header: {e}"))?.clone();
+    let missing = T::REQUIRED.iter()
+        .filter(|column| !headers.iter().any(|header| header == **column))
+        .copied()
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(format!("Missing CSV columns: {}", missing.join(", ")));
+    }
+
+    let mut parsed = Parsed::default();
+    let mut first_seen = HashMap::new();
+    let mut batch = Vec::with_capacity(ROWS_PER_CHUNK);
+    let mut record = csv::StringRecord::new();
+
+    loop {
+        match reader.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => parsed.received += 1,
+            Err(e) if e.is_io_error() => return Err(format!("Failed to read CSV: {e}")),
+            Err(e) => {
+                parsed.received += 1;
+                parsed.reject(e.position().map_or(0, |position| position.line()), e.to_string());
+                continue;
+            }
+        }
+
+        let line = record.position().map_or(0, |position| position.line());
+        let row = match record.deserialize::<T>(Some(&headers)) {
+            Ok(row) => row,
+            Err(e) => {
+                parsed.reject(line, e.to_string());
+                continue;
+            }
+        };
+
+        if let Err(error) = row.validate() {
+            parsed.reject(line, error);
+            continue;
+        }
+        if let Some(first) = first_seen.insert(row.id(), line) {
+            first_seen.insert(row.id(), first);
+            parsed.reject(line, format!("duplicate id {} (first on line {first})", row.id()));
+            continue;
+        }
+
+        batch.push(row);
+        if batch.len() == ROWS_PER_CHUNK {
+            let full = std::mem::replace(&mut batch, Vec::with_capacity(ROWS_PER_CHUNK));
+            if batches.blocking_send(full).is_err() {
+                return Ok(parsed);
+            }
+        }
+    }
+
+    if !batch.is_empty() {
+        let _ = batches.blocking_send(batch);
+    }
+    Ok(parsed)
+}
+
+/// Stream a CSV request body into the table of `T` within one transaction.
+///
+/// Invalid rows are reported and left out, or fail the whole import when
+/// `strict` is set; database errors such as conflicts always do.
+pub async fn import<T: CsvRow>(
+    store: &dyn OrderStore,
+    body: Body,
+    params: &ImportParams,
+    options: T::Options,
+) -> Result<(StatusCode, Json<ImportReport>), (StatusCode, String)> {
+    let stream = body.into_data_stream()
+        .map_err(std::io::Error::other);
+    let input = SyncIoBridge::new(StreamReader::new(stream));
+    let (sender, receiver) = mpsc::channel(4);
+    let (decide, proceed) = oneshot::channel();
+    let strict = params.strict;
+
+    // The store commits only once the whole body has been read and checked.
+    let parser = async move {
+        let parsed = tokio::task::spawn_blocking(move || parse_rows::<T>(input, sender))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+            .and_then(|parsed| parsed.map_err(|e| (StatusCode::BAD_REQUEST, e)));
+        let _ = decide.send(parsed.as_ref().is_ok_and(|parsed| !strict || parsed.rejected == 0));
+        parsed
+    };
+    let (written, parsed) = tokio::join!(T::import(store, receiver, params.on_conflict, options, proceed), parser);
+    let parsed = parsed?;
+    let written = written.map_err(orders::db_error)?;
+
+    let mut report = ImportReport {
+        summary: InsertSummary { received: parsed.received, ..Default::default() },
+        rejected: parsed.rejected,
+        errors: parsed.errors,
+    };
+    let Some((inserted, updated)) = written else {
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, Json(report)));
+    };
+
+    report.summary.inserted = inserted;
+    report.summary.updated = updated;
+    report.summary.skipped = report.summary.received - report.rejected - inserted - updated;
+    Ok((StatusCode::OK, Json(report)))
+}
+
+pub async fn export_orders(store: &dyn OrderStore, format: Format) -> Result<Response, (StatusCode, String)> {
+    match format {
+        Format::Json => Ok(Json(store.list_orders().await.map_err(orders::db_error)?).into_response()),
+        Format::Csv => {
+            let chunks = store.orders_csv().await.map_err(orders::db_error)?;
+            Ok(csv_response("orders", Body::from_stream(chunks)))
+        }
+    }
+}
+
+pub async fn export_regions(store: &dyn OrderStore, format: Format) -> Result<Response, (StatusCode, String)> {
+    match format {
+        Format::Json => Ok(Json(store.list_regions().await.map_err(orders::db_error)?).into_response()),
+        Format::Csv => {
+            let chunks = store.regions_csv().await.map_err(orders::db_error)?;
+            Ok(csv_response("regions", Body::from_stream(chunks)))
+        }
+    }
+}
+
+/// Serialize report rows as JSON or, with a header line, as CSV.
+pub fn report<T: Serialize>(name: &str, rows: &[T], format: Format) -> Result<Response, (StatusCode, String)> {
+    match format {
+        Format::Json => Ok(Json(rows).into_response()),
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for row in rows {
+                writer.serialize(row)
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            }
+            let csv = writer.into_inner()
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            Ok(csv_response(name, Body::from(csv)))
+        }
+    }
+}
+
+fn csv_response(name: &str, body: Body) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{name}.csv\"")),
+        ],
+        body,
+    ).into_response()
+}
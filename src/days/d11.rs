@@ -1,32 +1,36 @@
+use std::time::Duration;
 use axum::{
-    routing::post,
+    routing::{get, post},
     Router,
-    extract::Multipart,
+    Json,
+    extract::{Multipart, Path, Query, State},
+    http::{header, StatusCode},
 };
 use axum::response::IntoResponse;
-use serde::{Serialize};
-use tower_http::services::ServeDir;
+use axum_extra::TypedHeader;
+use headers::{IfModifiedSince, IfNoneMatch, Range};
+use image::io::Limits;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 
-use image::{
-    io::Reader,
-    GenericImageView,
-};
-use std::io::Cursor;
+use crate::days::assets::{self, AssetMeta, AssetStore};
+use crate::days::imaging::{self, Analysis, AnalysisOptions, Operation, OutputFormat};
 
-#[derive(Serialize)]
-struct CountResponse {
-    elf: i32,
-    #[serde(rename(serialize = "elf on a shelf"))]
-    elf_on_a_shelf: i32,
-    #[serde(rename(serialize = "shelf with no elf on it"))]
-    shelf_with_no_elf_on_it: i32,
-}
+const ASSETS_DIR: &str = "assets";
 
 pub fn get_routes() -> Router {
+
+    let store = AssetStore::new(ASSETS_DIR);
+
     Router::new()
         .route("/11/red_pixels", post(red_pixels))
-        .nest_service("/11/assets", ServeDir::new("assets"))
-
+        .route("/11/red_pixels/:id", get(red_pixels_asset))
+        .route("/11/analyze", post(analyze))
+        .route("/11/transform", post(transform))
+        .route("/11/assets", get(list_assets).post(upload_assets))
+        .route("/11/assets/:id", get(get_asset).delete(delete_asset))
+        .route("/11/assets/:id/meta", get(get_asset_meta))
+        .with_state(store)
 }
 
 async fn red_pixels(mut multipart: Multipart) -> impl IntoResponse {
@@ -34,18 +38,227 @@ async fn red_pixels(mut multipart: Multipart) -> impl IntoResponse {
 
     while let Some(field) = multipart.next_field().await.unwrap() {
         let data = field.bytes().await.unwrap();
-        let reader = Reader::new(Cursor::new(&data))
-            .with_guessed_format()
-            .expect("This will never fail using Cursor");
-        let img = reader.decode().expect("Failed to read image");
-
-        red_pixels = img.pixels()
-            .filter(|x| {
-                let [r, g, b, _] = x.2 .0;
-                r as u16 > (g as u16 + b as u16)
-            })
-            .count() as u32;
+        let (img, _) = imaging::decode(&data).expect("Failed to read image");
+
+        red_pixels += imaging::count_red_pixels(&img);
     }
 
     red_pixels.to_string()
 }
+
+/// Name of the optional multipart field carrying `AnalysisOptions` as JSON.
+const OPTIONS_FIELD: &str = "options";
+
+#[derive(Serialize)]
+struct FieldAnalysis {
+    field: Option<String>,
+    file_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    analysis: Option<Analysis>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+async fn analyze(mut multipart: Multipart) -> Result<Json<Vec<FieldAnalysis>>, (StatusCode, String)> {
+    let mut options = AnalysisOptions::default();
+    let mut uploads = vec![];
+
+    while let Some(field) = multipart.next_field().await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))? {
+        let name = field.name().map(str::to_string);
+        let file_name = field.file_name().map(str::to_string);
+        let data = field.bytes().await
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        if name.as_deref() == Some(OPTIONS_FIELD) {
+            options = serde_json::from_slice(&data)
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid options: {e}")))?;
+        } else {
+            uploads.push((name, file_name, data));
+        }
+    }
+
+    let results = tokio::task::spawn_blocking(move || {
+        uploads.into_iter()
+            .map(|(field, file_name, data)| match imaging::decode_with_limits(&data, input_limits()) {
+                Ok((img, format)) => FieldAnalysis {
+                    field,
+                    file_name,
+                    analysis: Some(imaging::analyze(&img, format, &options)),
+                    error: None,
+                },
+                Err(e) => FieldAnalysis {
+                    field,
+                    file_name,
+                    analysis: None,
+                    error: Some(e),
+                },
+            })
+            .collect()
+    })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(results))
+}
+
+/// Name of the multipart field carrying the JSON list of `Operation`s.
+const PIPELINE_FIELD: &str = "pipeline";
+/// Largest accepted input and produced output side, in pixels.
+const MAX_INPUT_DIMENSION: u32 = 8192;
+const MAX_OUTPUT_DIMENSION: u32 = 4096;
+/// Decoder limits for uploads, checked against the header before any pixels
+/// are allocated.
+fn input_limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_INPUT_DIMENSION);
+    limits.max_image_height = Some(MAX_INPUT_DIMENSION);
+    limits
+}
+
+/// Time budget for decoding, transforming and encoding a single upload. Only
+/// the response is cut short: the blocking task cannot be cancelled and runs
+/// to completion, bounded by the dimension and blur limits instead.
+const PROCESSING_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+struct TransformParams {
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default = "default_quality")]
+    quality: u8,
+}
+
+fn default_quality() -> u8 {
+    85
+}
+
+async fn transform(
+    Query(params): Query<TransformParams>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut operations: Vec<Operation> = vec![];
+    let mut upload = None;
+
+    while let Some(field) = multipart.next_field().await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))? {
+        let is_pipeline = field.name() == Some(PIPELINE_FIELD);
+        let data = field.bytes().await
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        if is_pipeline {
+            operations = serde_json::from_slice(&data)
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid pipeline: {e}")))?;
+        } else if upload.is_none() {
+            upload = Some(data);
+        }
+    }
+    let data = upload.ok_or((StatusCode::BAD_REQUEST, "No image uploaded".to_string()))?;
+
+    let task = tokio::task::spawn_blocking(move || {
+        let (img, _) = imaging::decode_with_limits(&data, input_limits())?;
+        let img = imaging::transform(img, &operations, MAX_OUTPUT_DIMENSION)?;
+        imaging::encode(&img, params.format, params.quality)
+    });
+
+    let encoded = tokio::time::timeout(PROCESSING_TIMEOUT, task)
+        .await
+        .map_err(|_| (StatusCode::SERVICE_UNAVAILABLE, "Image processing timed out".to_string()))?
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+
+    Ok(([(header::CONTENT_TYPE, params.format.mime_type())], encoded))
+}
+
+async fn list_assets(State(store): State<AssetStore>) -> Result<Json<Vec<AssetMeta>>, (StatusCode, String)> {
+    store.list()
+        .await
+        .map(Json)
+        .map_err(assets::internal_error)
+}
+
+async fn upload_assets(
+    State(store): State<AssetStore>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut stored = vec![];
+
+    while let Some(field) = multipart.next_field().await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))? {
+        let file_name = field.file_name().map(str::to_string);
+        let data = field.bytes().await
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        stored.push(store.put(file_name, &data).await.map_err(assets::internal_error)?);
+    }
+
+    Ok((StatusCode::CREATED, Json(stored)))
+}
+
+#[derive(Deserialize)]
+struct VariantParams {
+    width: Option<u32>,
+    height: Option<u32>,
+    #[serde(default)]
+    format: OutputFormat,
+}
+
+async fn get_asset(
+    State(store): State<AssetStore>,
+    Path(id): Path<String>,
+    Query(variant): Query<VariantParams>,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
+    range: Option<TypedHeader<Range>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let asset = match (variant.width, variant.height) {
+        (None, None) => store.get(&id).await.map_err(assets::internal_error)?,
+        (width, height) => {
+            // A missing side is bounded by the other one; `resize` keeps the aspect ratio.
+            let width = width.or(height).unwrap_or_default();
+            let height = height.or(Some(width)).unwrap_or_default();
+            store.thumbnail(&id, width, height, variant.format).await?
+        }
+    };
+    let (meta, data) = asset.ok_or((StatusCode::NOT_FOUND, format!("Asset {id} not found")))?;
+
+    Ok(assets::conditional_response(&meta, data, if_none_match, if_modified_since, range))
+}
+
+async fn get_asset_meta(
+    State(store): State<AssetStore>,
+    Path(id): Path<String>,
+) -> Result<Json<AssetMeta>, (StatusCode, String)> {
+    store.get(&id)
+        .await
+        .map_err(assets::internal_error)?
+        .map(|(meta, _)| Json(meta))
+        .ok_or((StatusCode::NOT_FOUND, format!("Asset {id} not found")))
+}
+
+async fn delete_asset(
+    State(store): State<AssetStore>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match store.delete(&id).await.map_err(assets::internal_error)? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err((StatusCode::NOT_FOUND, format!("Asset {id} not found"))),
+    }
+}
+
+async fn red_pixels_asset(
+    State(store): State<AssetStore>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let (meta, _) = store.get(&id)
+        .await
+        .map_err(assets::internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, format!("Asset {id} not found")))?;
+    let red_pixels = meta.red_pixels
+        .ok_or((StatusCode::UNPROCESSABLE_ENTITY, format!("Asset {id} is not an image")))?;
+
+    Ok(Json(json!({
+        "id": meta.id,
+        "red_pixels": red_pixels,
+    })))
+}
@@ -1,55 +1,250 @@
 use axum::{routing::{get, post}, Router, Json};
 use axum::response::IntoResponse;
-use std::sync::Arc;
-use axum::extract::{Path, State};
-use chrono::{DateTime, Local, Datelike, NaiveTime, Utc};
-use ulid::Ulid;
+use axum::http::StatusCode;
+use std::sync::{Arc, Mutex};
+use axum::extract::{Path, Query, State};
+use chrono::{DateTime, Duration, Utc};
+use ulid::{Generator, Ulid};
 use uuid::Uuid;
+use serde::Deserialize;
 use serde_json::{json};
 
-use shuttle_persist::PersistInstance;
+use shuttle_persist::{PersistError, PersistInstance};
+use crate::days::kv::{self, Item, KeyInfo, KvError, KvStore};
+use crate::days::namespace::Namespace;
+use crate::days::timers::{Timer, TimerView};
+use crate::days::ulids::{self, Analytics, Bucket, Conversion, DatePredicate, Repr};
 
 struct AppState {
     persist: PersistInstance,
+    kv: KvStore,
+    // Shared so ULIDs minted within the same millisecond stay ordered.
+    generator: Mutex<Generator>,
+    // Serializes timer writes so concurrent load/modify/save cycles don't lose updates.
+    timer_lock: Mutex<()>,
 }
 
 pub fn get_routes(
     persist: PersistInstance
 ) -> Router {
 
-    let state = Arc::new(AppState { persist });
+    let state = Arc::new(AppState {
+        kv: KvStore::new(persist.clone()),
+        persist,
+        generator: Mutex::new(Generator::new()),
+        timer_lock: Mutex::new(()),
+    });
 
     Router::new()
         .route("/12/save/:key", post(save_key))
         .route("/12/load/:key",  get(load_key))
         .route("/12/ulids", post(convert_ulids))
         .route("/12/ulids/:weekday", post(calculate_ulids))
+        .route("/12/ulids/new", post(mint_ulids))
+        .route("/12/ulids/convert", post(convert_any))
+        .route("/12/ulids/analytics", post(ulid_analytics))
+        .route("/12/kv", get(list_kv))
+        .route("/12/kv/:key", get(get_kv).put(put_kv).delete(delete_kv))
+        .route("/12/timers", get(list_timers))
+        .route("/12/timers/:name", get(get_timer).delete(delete_timer))
+        .route("/12/timers/:name/start", post(start_timer))
+        .route("/12/timers/:name/lap", post(lap_timer))
+        .route("/12/timers/:name/pause", post(pause_timer))
+        .route("/12/timers/:name/resume", post(resume_timer))
+        .route("/12/timers/:name/stop", post(stop_timer))
 
         .with_state(state)
 }
 
+/// Map persist failures to responses: unknown keys are 404, invalid ones 400.
+fn persist_error(key: &str, e: PersistError) -> (StatusCode, String) {
+    match e {
+        PersistError::Open(ref io) if io.kind() == std::io::ErrorKind::NotFound =>
+            (StatusCode::NOT_FOUND, format!("Key {key} not found")),
+        PersistError::RemoveFile(ref io) if io.kind() == std::io::ErrorKind::NotFound =>
+            (StatusCode::NOT_FOUND, format!("Key {key} not found")),
+        PersistError::InvalidKey => (StatusCode::BAD_REQUEST, format!("Invalid key {key}")),
+        e => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
 async fn save_key(State(state): State<Arc<AppState>>, Path(key): Path<String>) -> impl IntoResponse  {
     // Save key with current time.
     state
         .persist
-        .save::<NaiveTime>(
-            &key,  Local::now().time()
+        .save::<DateTime<Utc>>(
+            &Namespace::Plain.key(&key),  Utc::now()
         )
-        .map_err(|e| (e.to_string()))
+        .map_err(|e| persist_error(&key, e))
     // Return nothing.
 }
 
-async fn load_key(State(state): State<Arc<AppState>>, Path(key): Path<String>) -> impl IntoResponse  {
+async fn load_key(State(state): State<Arc<AppState>>, Path(key): Path<String>) -> Result<String, (StatusCode, String)>  {
     // Get elapsed time from stored value with key.
-    let elapsed = Local::now().time() - state
+    let elapsed = Utc::now() - state
         .persist
-        .load::<NaiveTime>(
-            &key
-        ).unwrap();
+        .load::<DateTime<Utc>>(
+            &Namespace::Plain.key(&key)
+        )
+        .map_err(|e| persist_error(&key, e))?;
+
+    Ok(elapsed.num_seconds().to_string())
+}
+
+fn kv_error(key: &str, e: KvError) -> (StatusCode, String) {
+    match e {
+        KvError::NotFound => (StatusCode::NOT_FOUND, format!("Key {key} not found")),
+        KvError::VersionMismatch { expected, actual } => (
+            StatusCode::PRECONDITION_FAILED,
+            format!("Key {key} is at version {actual}, expected {expected}"),
+        ),
+        KvError::ValueTooLarge(size) => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Value is {size} bytes, the limit is {}", kv::MAX_VALUE_BYTES),
+        ),
+        KvError::QuotaExceeded(limit) => (StatusCode::INSUFFICIENT_STORAGE, format!("Quota exceeded: {limit}")),
+        KvError::Persist(e) => persist_error(key, e),
+    }
+}
+
+#[derive(Deserialize)]
+struct ListParams {
+    #[serde(default)]
+    prefix: String,
+}
+
+#[derive(Deserialize)]
+struct WriteParams {
+    /// Time to live in seconds.
+    ttl: Option<u32>,
+    /// Expected current version for compare-and-swap, 0 if the key must not exist.
+    version: Option<u64>,
+}
+
+async fn list_kv(State(state): State<Arc<AppState>>, Query(params): Query<ListParams>) -> Result<Json<Vec<KeyInfo>>, (StatusCode, String)> {
+    state.kv
+        .list(&params.prefix)
+        .map(Json)
+        .map_err(|e| kv_error(&params.prefix, e))
+}
+
+async fn get_kv(State(state): State<Arc<AppState>>, Path(key): Path<String>) -> Result<Json<Item>, (StatusCode, String)> {
+    state.kv
+        .get(&key)
+        .map(Json)
+        .map_err(|e| kv_error(&key, e))
+}
+
+async fn put_kv(
+    State(state): State<Arc<AppState>>,
+    Path(key): Path<String>,
+    Query(params): Query<WriteParams>,
+    Json(value): Json<serde_json::Value>,
+) -> Result<Json<KeyInfo>, (StatusCode, String)> {
+    let ttl = params.ttl.map(|secs| Duration::seconds(secs.into()));
+    state.kv
+        .put(&key, &value, ttl, params.version)
+        .await
+        .map(Json)
+        .map_err(|e| kv_error(&key, e))
+}
+
+async fn delete_kv(
+    State(state): State<Arc<AppState>>,
+    Path(key): Path<String>,
+    Query(params): Query<WriteParams>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    state.kv
+        .delete(&key, params.version)
+        .await
+        .map_err(|e| kv_error(&key, e))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn load_timer(state: &AppState, name: &str) -> Result<Timer, (StatusCode, String)> {
+    state.persist
+        .load::<Timer>(&Namespace::Timer.key(name))
+        .map_err(|e| persist_error(name, e))
+}
+
+fn save_timer(state: &AppState, timer: &Timer) -> Result<Json<TimerView>, (StatusCode, String)> {
+    state.persist
+        .save(&Namespace::Timer.key(&timer.name), timer)
+        .map_err(|e| persist_error(&timer.name, e))?;
+
+    Ok(Json(timer.view(Utc::now())))
+}
+
+async fn list_timers(State(state): State<Arc<AppState>>) -> Result<Json<Vec<TimerView>>, (StatusCode, String)> {
+    let now = Utc::now();
+    let stored = state.persist
+        .list()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut names = Namespace::Timer.keys(stored);
+    names.sort();
+
+    let mut timers = vec![];
+    for name in &names {
+        match state.persist.load::<Timer>(&Namespace::Timer.key(name)) {
+            Ok(timer) => timers.push(timer.view(now)),
+            // Deleted since listing, or not readable as a timer.
+            Err(PersistError::Open(ref io)) if io.kind() == std::io::ErrorKind::NotFound => (),
+            Err(PersistError::Deserialize(_)) => (),
+            Err(e) => return Err(persist_error(name, e)),
+        }
+    }
+
+    Ok(Json(timers))
+}
+
+async fn get_timer(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Result<Json<TimerView>, (StatusCode, String)> {
+    Ok(Json(load_timer(&state, &name)?.view(Utc::now())))
+}
+
+async fn delete_timer(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Result<StatusCode, (StatusCode, String)> {
+    let _guard = state.timer_lock.lock().unwrap();
+    state.persist
+        .remove(&Namespace::Timer.key(&name))
+        .map_err(|e| persist_error(&name, e))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// (Re)starts a timer, discarding any previous laps.
+async fn start_timer(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Result<Json<TimerView>, (StatusCode, String)> {
+    let _guard = state.timer_lock.lock().unwrap();
+    save_timer(&state, &Timer::start(name, Utc::now()))
+}
+
+fn update_timer(
+    state: &AppState,
+    name: &str,
+    action: fn(&mut Timer, DateTime<Utc>) -> Result<(), String>,
+) -> Result<Json<TimerView>, (StatusCode, String)> {
+    let _guard = state.timer_lock.lock().unwrap();
+    let mut timer = load_timer(state, name)?;
+    action(&mut timer, Utc::now()).map_err(|e| (StatusCode::CONFLICT, e))?;
+    save_timer(state, &timer)
+}
 
-    elapsed.num_seconds().to_string()
+async fn lap_timer(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Result<Json<TimerView>, (StatusCode, String)> {
+    update_timer(&state, &name, Timer::lap)
 }
 
+async fn pause_timer(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Result<Json<TimerView>, (StatusCode, String)> {
+    update_timer(&state, &name, Timer::pause)
+}
+
+async fn resume_timer(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Result<Json<TimerView>, (StatusCode, String)> {
+    update_timer(&state, &name, Timer::resume)
+}
+
+async fn stop_timer(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Result<Json<TimerView>, (StatusCode, String)> {
+    update_timer(&state, &name, Timer::stop)
+}
+
+/// Invalid entries are skipped; `/12/ulids/convert` reports them instead.
 async fn convert_ulids(Json(body): Json<Vec<String>>) -> impl IntoResponse  {
 
     let ulids = body.into_iter()
@@ -65,44 +260,120 @@ async fn convert_ulids(Json(body): Json<Vec<String>>) -> impl IntoResponse  {
     Json(uuids)
 }
 
-async fn calculate_ulids(
-    Path(weekday): Path<u32>,
-    Json(body): Json<Vec<String>>
-) -> impl IntoResponse  {
+const MAX_MINT_COUNT: usize = 1000;
 
-    let ulids = body.into_iter()
-        .map(|el| Ulid::from_string(&el))
-        .filter_map(Result::ok)
-        .collect::<Vec<_>>();
+#[derive(Deserialize)]
+struct MintParams {
+    #[serde(default = "default_mint_count")]
+    count: usize,
+    /// Unix milliseconds or RFC 3339; defaults to now.
+    timestamp: Option<String>,
+}
 
-    let mut n_christmas = 0;
-    let mut n_weekday = 0;
-    let mut n_future = 0;
-    let mut n_lsb = 0;
+fn default_mint_count() -> usize {
+    1
+}
 
-    for ulid in ulids {
-        let time: DateTime<Utc> = ulid.datetime().into();
-        //println!("TIME: {:?}", time);
-        if time.month() == 12 && time.day() == 24 {
-            n_christmas += 1;
-        }
-        if time.weekday().num_days_from_monday() == weekday {
-            n_weekday += 1;
-        }
-        if time > Utc::now() {
-            n_future += 1;
+/// Mint `count` ULIDs, strictly increasing even within a single millisecond.
+async fn mint_ulids(State(state): State<Arc<AppState>>, Query(params): Query<MintParams>) -> Result<Json<Vec<Conversion>>, (StatusCode, String)> {
+    if params.count == 0 || params.count > MAX_MINT_COUNT {
+        return Err((StatusCode::BAD_REQUEST, format!("count must be between 1 and {MAX_MINT_COUNT}")));
+    }
+
+    let ulids = match params.timestamp {
+        // A fresh generator, as the shared one would bump past-dated ULIDs to its last timestamp.
+        Some(timestamp) => {
+            let time = ulids::to_system_time(
+                ulids::parse_timestamp(&timestamp).map_err(|e| (StatusCode::BAD_REQUEST, e))?
+            );
+            let mut generator = Generator::new();
+            (0..params.count)
+                .map(|_| generator.generate_from_datetime(time))
+                .collect::<Result<Vec<_>, _>>()
         }
-        if ulid.random() & 1 == 1 {
-            n_lsb += 1;
+        None => {
+            let mut generator = state.generator.lock().unwrap();
+            (0..params.count)
+                .map(|_| generator.generate())
+                .collect::<Result<Vec<_>, _>>()
         }
     }
+        .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+
+    Ok(Json(ulids.into_iter()
+        .map(|ulid| Conversion::from_ulid(&ulid.to_string(), ulid))
+        .collect()))
+}
+
+#[derive(Deserialize)]
+struct ConvertParams {
+    #[serde(default)]
+    from: Repr,
+}
+
+/// Convert each input to every representation, reporting invalid ones in place.
+async fn convert_any(Query(params): Query<ConvertParams>, Json(body): Json<Vec<String>>) -> impl IntoResponse {
+    let conversions = body.iter()
+        .map(|input| Conversion::new(input, params.from))
+        .collect::<Vec<_>>();
+    let invalid = conversions.iter().filter(|c| !c.is_valid()).count();
+
+    Json(json!({
+        "invalid": invalid,
+        "results": conversions,
+    }))
+}
+
+#[derive(Deserialize)]
+struct TzParams {
+    /// IANA time zone the dates are evaluated in.
+    #[serde(default = "default_tz")]
+    tz: String,
+}
+
+fn default_tz() -> String {
+    "UTC".to_string()
+}
+
+async fn calculate_ulids(
+    Path(weekday): Path<u32>,
+    Query(params): Query<TzParams>,
+    Json(body): Json<Vec<String>>
+) -> Result<impl IntoResponse, (StatusCode, String)>  {
+
+    let tz = ulids::parse_tz(&params.tz).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let predicates = [
+        DatePredicate::Dates { name: "christmas eve".to_string(), dates: vec![(12, 24)] },
+        DatePredicate::Weekdays { name: "weekday".to_string(), weekdays: vec![weekday] },
+    ];
+    let stats = ulids::analyze(&body, tz, &predicates, None, Utc::now());
 
     let res = json!({
-        "christmas eve": n_christmas,
-        "weekday": n_weekday,
-        "in the future": n_future,
-        "LSB is 1": n_lsb
+        "christmas eve": stats.predicates["christmas eve"],
+        "weekday": stats.predicates["weekday"],
+        "in the future": stats.in_the_future,
+        "LSB is 1": stats.lsb_is_1
     });
 
-    Json(res)
+    Ok(Json(res))
+}
+
+#[derive(Deserialize)]
+struct AnalyticsRequest {
+    ulids: Vec<String>,
+    #[serde(default = "default_tz")]
+    tz: String,
+    #[serde(default = "default_date_predicates")]
+    predicates: Vec<DatePredicate>,
+    histogram: Option<Bucket>,
+}
+
+fn default_date_predicates() -> Vec<DatePredicate> {
+    vec![DatePredicate::Dates { name: "christmas eve".to_string(), dates: vec![(12, 24)] }]
+}
+
+async fn ulid_analytics(Json(request): Json<AnalyticsRequest>) -> Result<Json<Analytics>, (StatusCode, String)> {
+    let tz = ulids::parse_tz(&request.tz).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    Ok(Json(ulids::analyze(&request.ulids, tz, &request.predicates, request.histogram, Utc::now())))
 }
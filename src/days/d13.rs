@@ -1,113 +1,169 @@
 use axum::{routing::{get, post}, Router};
 use axum::response::IntoResponse;
-use axum::extract::{State};
+use axum::extract::{Query, State};
+use axum::body::Body;
+use axum::http::StatusCode;
+use axum::response::Response;
 use axum::Json;
-use sqlx::{PgPool};
+use std::sync::Arc;
 use serde::Deserialize;
 use serde_json::json;
+use crate::days::orders::{self, GroupBy, InsertParams, InsertSummary, Interval, MissingRegions, Order, OrderFilter,
+                          OrderStats, OrderStore, ResetParams, ResetSummary, SeriesPoint};
+use crate::days::csv_io::{self, ExportParams, ImportParams, ImportReport};
+use crate::days::ulids;
+
+/// Percentiles reported when none are requested.
+const DEFAULT_PERCENTILES: [f64; 3] = [50.0, 90.0, 99.0];
 
 
 #[derive(Clone)]
 pub struct AppState {
-    pub pool: PgPool,
+    pub store: Arc<dyn OrderStore>,
 }
 
 pub fn get_routes(
-    pool: PgPool
+    store: Arc<dyn OrderStore>
 ) -> Router {
 
-    let state = AppState { pool };
+    let state = AppState { store };
 
     Router::new()
         .route("/13/sql", get(query_sql))
         .route("/13/reset", post(reset_sql))
-        .route("/13/orders", post(post_order))
+        .route("/13/orders", get(export_orders).post(post_order))
+        .route("/13/orders/csv", post(import_orders))
         .route("/13/orders/total", get(sum_order))
         .route("/13/orders/popular", get(popular_order))
+        .route("/13/orders/analytics", get(order_analytics))
+        .route("/13/orders/series", get(order_series))
         .with_state(state)
 }
 
 async fn query_sql(State(state): State<AppState>) -> impl IntoResponse  {
-    let query = sqlx::query!("SELECT 20231213 number")
-        .fetch_one(&state.pool)
+    let query = state.store.select(20231213)
         .await
-        .unwrap()
-        .number
         .unwrap();
 
     query.to_string()
 }
 
-async fn reset_sql(State(state): State<AppState>) -> impl IntoResponse  {
-    let _ = sqlx::query!("DROP TABLE IF EXISTS orders")
-        .execute(&state.pool)
+async fn reset_sql(
+    State(state): State<AppState>,
+    Query(params): Query<ResetParams>,
+) -> Result<Json<ResetSummary>, (StatusCode, String)> {
+    let fixture = match params.seed {
+        Some(name) => Some(orders::load_fixture(&name).await?),
+        None => None,
+    };
+
+    state.store.reset(false, fixture)
         .await
-        .unwrap();
+        .map(Json)
+        .map_err(orders::db_error)
+}
 
-    let _ = sqlx::query!(
-    r"
-  CREATE TABLE orders (
-    id INT PRIMARY KEY,
-    region_id INT,
-    gift_name VARCHAR(50),
-    quantity INT
-  )"
-  )
-        .execute(&state.pool)
-        .await.unwrap();
-
-    "".to_string()
+pub async fn post_order(
+    State(state): State<AppState>,
+    Query(params): Query<InsertParams>,
+    Json(payload): Json<Vec<Order>>,
+) -> Result<Json<InsertSummary>, (StatusCode, String)>
+{
+    state.store.insert_orders(&payload, params.on_conflict, MissingRegions::Create)
+        .await
+        .map(Json)
+        .map_err(orders::db_error)
 }
 
-#[derive(Deserialize, Debug)]
-pub struct Order {
-    pub id: i32,
-    pub region_id: i32,
-    pub gift_name: String,
-    pub quantity: i32,
+pub async fn import_orders(
+    State(state): State<AppState>,
+    Query(params): Query<ImportParams>,
+    body: Body,
+) -> Result<(StatusCode, Json<ImportReport>), (StatusCode, String)> {
+    csv_io::import::<Order>(state.store.as_ref(), body, &params, MissingRegions::Create).await
 }
 
-pub async fn post_order(State(state): State<AppState>, Json(payload): Json<Vec<Order>>) -> impl IntoResponse
-{
-    for el in payload {
-        let _ = sqlx::query!(
-            "INSERT INTO orders (id, region_id, gift_name, quantity) VALUES ($1, $2, $3, $4)",
-                el.id,
-                el.region_id,
-                el.gift_name,
-                el.quantity
-            )
-            .execute(&state.pool)
-            .await.unwrap();
-    }
-    "".to_string()
+pub async fn export_orders(
+    State(state): State<AppState>,
+    Query(params): Query<ExportParams>,
+) -> Result<Response, (StatusCode, String)> {
+    csv_io::export_orders(state.store.as_ref(), params.format).await
 }
 
-async fn sum_order(State(state): State<AppState>)  -> impl IntoResponse {
-    let total = sqlx::query!("SELECT SUM(quantity) total from orders")
-        .fetch_one(&state.pool)
+async fn sum_order(State(state): State<AppState>) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let total = state.store.total_quantity()
         .await
-        .unwrap()
-        .total
-        .unwrap();
+        .map_err(orders::db_error)?;
 
     let res = json!({
         "total": total,
     });
 
-    Json(res)
+    Ok(Json(res))
 }
 
-async fn popular_order(State(state): State<AppState>) -> impl IntoResponse {
-    let popular = sqlx::query!("SELECT gift_name, SUM(quantity) total from orders GROUP BY gift_name ORDER BY SUM(quantity) DESC")
-        .fetch_optional(&state.pool)
+async fn popular_order(State(state): State<AppState>) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let popular = state.store.popular_gift()
         .await
-        .unwrap()
-        .map(|item| item.gift_name);
+        .map_err(orders::db_error)?;
 
     let res = json!({
       "popular": popular,
     });
 
-    Json(res)
+    Ok(Json(res))
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct AnalyticsParams {
+    group_by: Option<GroupBy>,
+    top: Option<i64>,
+    /// Comma separated, e.g. `50,90,99`.
+    percentiles: Option<String>,
+}
+
+async fn order_analytics(
+    State(state): State<AppState>,
+    Query(filter): Query<OrderFilter>,
+    Query(params): Query<AnalyticsParams>,
+) -> Result<Json<OrderStats>, (StatusCode, String)> {
+    if params.top.is_some_and(|top| top < 0) {
+        return Err((StatusCode::BAD_REQUEST, "top must not be negative".to_string()));
+    }
+
+    let percentiles = match params.percentiles {
+        None => DEFAULT_PERCENTILES.to_vec(),
+        Some(list) => list.split(',')
+            .filter(|p| !p.trim().is_empty())
+            .map(|p| match p.trim().parse::<f64>() {
+                Ok(p) if (0.0..=100.0).contains(&p) => Ok(p),
+                _ => Err((StatusCode::BAD_REQUEST, format!("Invalid percentile {p}"))),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+
+    state.store.order_stats(&filter, &percentiles, params.group_by, params.top)
+        .await
+        .map(Json)
+        .map_err(orders::db_error)
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct SeriesParams {
+    interval: Option<Interval>,
+    tz: Option<String>,
+}
+
+async fn order_series(
+    State(state): State<AppState>,
+    Query(filter): Query<OrderFilter>,
+    Query(params): Query<SeriesParams>,
+) -> Result<Json<Vec<SeriesPoint>>, (StatusCode, String)> {
+    let tz = ulids::parse_tz(params.tz.as_deref().unwrap_or("UTC"))
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    state.store.order_series(&filter, params.interval.unwrap_or(Interval::Day), tz)
+        .await
+        .map(Json)
+        .map_err(orders::db_error)
 }
@@ -1,8 +1,11 @@
 use askama::Template;
 use axum::{routing::{get, post}, Router};
-use axum::response::IntoResponse;
-use axum::Json;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
 use serde::{Deserialize, Serialize};
+use crate::days::markdown::{self, SanitizePolicy};
+use crate::days::security::{CspNonce, SANDBOX_POLICY};
 
 
 pub fn get_routes() -> Router {
@@ -11,6 +14,14 @@ pub fn get_routes() -> Router {
         .route("/14", get(axum::http::StatusCode::OK))
         .route("/14/unsafe", post(unsafe_content))
         .route("/14/safe", post(safe_content))
+        .route("/14/markdown", post(markdown_content))
+        .route("/14/render", post(render_layout))
+}
+
+const DEFAULT_TITLE: &str = "CCH23 Day 14";
+
+fn default_title() -> String {
+    DEFAULT_TITLE.to_string()
 }
 
 #[derive(Deserialize, Serialize, Template)]
@@ -18,16 +29,115 @@ pub fn get_routes() -> Router {
 struct Content {
     content: String,
     not_safe: Option<bool>,
+    #[serde(default = "default_title")]
+    title: String,
 }
 
+/// Raw user HTML is sandboxed, so injected scripts cannot run.
 async fn unsafe_content(Json(data): Json<Content>) -> impl IntoResponse {
     let data = Content {
         not_safe: Some(true),
         ..data
     };
-    data.into_response()
+    ([(header::CONTENT_SECURITY_POLICY, SANDBOX_POLICY)], data)
 }
 
 async fn safe_content(Json(data): Json<Content>) -> impl IntoResponse {
     data.into_response()
-}
\ No newline at end of file
+}
+
+#[derive(Deserialize)]
+struct MarkdownContent {
+    content: String,
+    #[serde(default)]
+    policy: SanitizePolicy,
+    #[serde(default = "default_title")]
+    title: String,
+}
+
+async fn markdown_content(Json(data): Json<MarkdownContent>) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let content = markdown::render(&data.content, &data.policy)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    // Already sanitized, so it is embedded as is.
+    Ok(Content {
+        content,
+        not_safe: Some(true),
+        title: data.title,
+    })
+}
+
+/// Templates under `templates/layouts`, all extending `templates/base.html`.
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+enum Layout {
+    #[default]
+    Page,
+    Card,
+    List,
+    Email,
+}
+
+#[derive(Deserialize, Debug)]
+struct Item {
+    key: String,
+    value: String,
+}
+
+/// Variables shared by every layout; all of them are escaped.
+#[derive(Deserialize, Debug)]
+struct RenderRequest {
+    #[serde(default)]
+    layout: Layout,
+    #[serde(default = "default_title")]
+    title: String,
+    #[serde(default)]
+    body: String,
+    /// Shown in order, as a definition list or table depending on the layout.
+    #[serde(default)]
+    items: Vec<Item>,
+}
+
+#[derive(Template)]
+#[template(path = "layouts/page.html")]
+struct PageLayout<'a> {
+    title: &'a str,
+    body: &'a str,
+    items: &'a [Item],
+}
+
+#[derive(Template)]
+#[template(path = "layouts/card.html")]
+struct CardLayout<'a> {
+    title: &'a str,
+    body: &'a str,
+    items: &'a [Item],
+    /// For the inline stylesheet.
+    nonce: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "layouts/list.html")]
+struct ListLayout<'a> {
+    title: &'a str,
+    body: &'a str,
+    items: &'a [Item],
+}
+
+#[derive(Template)]
+#[template(path = "layouts/email.html")]
+struct EmailLayout<'a> {
+    title: &'a str,
+    body: &'a str,
+    items: &'a [Item],
+}
+
+async fn render_layout(Extension(nonce): Extension<CspNonce>, Json(data): Json<RenderRequest>) -> Response {
+    let (title, body, items) = (data.title.as_str(), data.body.as_str(), data.items.as_slice());
+    match data.layout {
+        Layout::Page => PageLayout { title, body, items }.into_response(),
+        Layout::Card => CardLayout { title, body, items, nonce: &nonce.0 }.into_response(),
+        Layout::List => ListLayout { title, body, items }.into_response(),
+        Layout::Email => EmailLayout { title, body, items }.into_response(),
+    }
+}
@@ -1,159 +1,273 @@
 use axum::{routing::{get, post}, Router};
-use axum::response::IntoResponse;
-use axum::extract::{Path, State};
+use axum::body::Body;
+use axum::response::Response;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
 use axum::Json;
-use sqlx::{PgPool, FromRow};
-use serde::{Serialize, Deserialize};
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::days::csv_io::{self, ExportParams, Format, ImportParams, ImportReport};
 use crate::days::d13;
+use crate::days::orders::{self, GiftRank, InsertParams, InsertSummary, Metric, MissingRegions, Order, OrderStore,
+                          Ranking, Region, RegionDeletion, RegionTopGifts, RegionUpdate, ResetParams, ResetSummary,
+                          TopGifts, TopGiftsQuery};
 
 pub fn get_routes(
-    pool: PgPool
+    store: Arc<dyn OrderStore>
 ) -> Router {
 
-    let state = d13::AppState { pool };
+    let state = d13::AppState { store };
 
     Router::new()
         .route("/18", get(axum::http::StatusCode::OK))
         .route("/18/reset", post(reset_sql))
-        .route("/18/orders", post(post_orders))
-        .route("/18/regions", post(post_regions))
+        .route("/18/orders", get(d13::export_orders).post(post_orders))
+        .route("/18/orders/csv", post(import_orders))
+        .route("/18/regions", get(list_regions).post(post_regions))
+        .route("/18/regions/csv", post(import_regions))
+        .route("/18/regions/:id", get(get_region).put(put_region).delete(delete_region))
         .route("/18/regions/total", get(sum_regions))
         .route("/18/regions/top_list/:number", get(top_gifts))
         .with_state(state)
 }
 
 
-async fn reset_sql(State(state): State<d13::AppState>) -> impl IntoResponse  {
-    let _ = sqlx::query!("DROP TABLE IF EXISTS regions")
-        .execute(&state.pool)
+async fn reset_sql(
+    State(state): State<d13::AppState>,
+    Query(params): Query<ResetParams>,
+) -> Result<Json<ResetSummary>, (StatusCode, String)> {
+    let fixture = match params.seed {
+        Some(name) => Some(orders::load_fixture(&name).await?),
+        None => None,
+    };
+
+    state.store.reset(true, fixture)
         .await
-        .unwrap();
+        .map(Json)
+        .map_err(orders::db_error)
+}
 
-    let _ = sqlx::query!("DROP TABLE IF EXISTS orders")
-        .execute(&state.pool)
+pub async fn post_orders(
+    State(state): State<d13::AppState>,
+    Query(params): Query<InsertParams>,
+    Json(payload): Json<Vec<Order>>,
+) -> Result<Json<InsertSummary>, (StatusCode, String)>
+{
+    state.store.insert_orders(&payload, params.on_conflict, MissingRegions::Reject)
         .await
-        .unwrap();
+        .map(Json)
+        .map_err(orders::db_error)
+}
+
+pub async fn post_regions(
+    State(state): State<d13::AppState>,
+    Query(params): Query<InsertParams>,
+    Json(payload): Json<Vec<Region>>,
+) -> Result<Json<InsertSummary>, (StatusCode, String)>
+{
+    state.store.insert_regions(&payload, params.on_conflict)
+        .await
+        .map(Json)
+        .map_err(orders::db_error)
+}
+
+/// Unlike Day 13, orders must refer to regions that exist.
+async fn import_orders(
+    State(state): State<d13::AppState>,
+    Query(params): Query<ImportParams>,
+    body: Body,
+) -> Result<(StatusCode, Json<ImportReport>), (StatusCode, String)> {
+    csv_io::import::<Order>(state.store.as_ref(), body, &params, MissingRegions::Reject).await
+}
 
+async fn import_regions(
+    State(state): State<d13::AppState>,
+    Query(params): Query<ImportParams>,
+    body: Body,
+) -> Result<(StatusCode, Json<ImportReport>), (StatusCode, String)> {
+    csv_io::import::<Region>(state.store.as_ref(), body, &params, ()).await
+}
 
-    let _ = sqlx::query!(
-            r"
-          CREATE TABLE regions (
-            id INT PRIMARY KEY,
-            name VARCHAR(50)
-          )")
-        .execute(&state.pool)
-        .await.unwrap();
+async fn list_regions(
+    State(state): State<d13::AppState>,
+    Query(params): Query<ExportParams>,
+) -> Result<Response, (StatusCode, String)> {
+    csv_io::export_regions(state.store.as_ref(), params.format).await
+}
 
-    let _ = sqlx::query!(
-            r"
-          CREATE TABLE orders (
-            id INT PRIMARY KEY,
-            region_id INT,
-            gift_name VARCHAR(50),
-            quantity INT
-          )")
-        .execute(&state.pool)
-        .await.unwrap();
+async fn get_region(
+    State(state): State<d13::AppState>,
+    Path(id): Path<i32>,
+) -> Result<Json<Region>, (StatusCode, String)> {
+    state.store.get_region(id)
+        .await
+        .map_err(orders::db_error)?
+        .map(Json)
+        .ok_or_else(|| region_not_found(id))
+}
 
-    "".to_string()
+async fn put_region(
+    State(state): State<d13::AppState>,
+    Path(id): Path<i32>,
+    Json(update): Json<RegionUpdate>,
+) -> Result<Json<Region>, (StatusCode, String)> {
+    state.store.update_region(id, &update)
+        .await
+        .map_err(orders::db_error)?
+        .map(Json)
+        .ok_or_else(|| region_not_found(id))
 }
 
-pub async fn post_orders(State(state): State<d13::AppState>, Json(payload): Json<Vec<d13::Order>>) -> impl IntoResponse
-{
-    for el in payload {
-        let _ = sqlx::query!(
-            "INSERT INTO orders (id, region_id, gift_name, quantity) VALUES ($1, $2, $3, $4)",
-                el.id,
-                el.region_id,
-                el.gift_name,
-                el.quantity
-            )
-            .execute(&state.pool)
-            .await.unwrap();
+async fn delete_region(
+    State(state): State<d13::AppState>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match state.store.delete_region(id).await.map_err(orders::db_error)? {
+        RegionDeletion::Deleted => Ok(StatusCode::NO_CONTENT),
+        RegionDeletion::NotFound => Err(region_not_found(id)),
+        RegionDeletion::HasOrders => Err((StatusCode::CONFLICT, format!("Region {id} still has orders"))),
     }
-    "".to_string()
 }
 
-#[derive(Deserialize, Debug)]
-pub struct Region {
-    pub id: i32,
-    pub name: String,
+fn region_not_found(id: i32) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("Region {id} not found"))
 }
 
-pub async fn post_regions(State(state): State<d13::AppState>, Json(payload): Json<Vec<Region>>) -> impl IntoResponse
-{
-    for el in payload {
-        let _ = sqlx::query!(
-            "INSERT INTO regions (id, name) VALUES ($1, $2)",
-                el.id,
-                el.name,
-            )
-            .execute(&state.pool)
-            .await.unwrap();
-    }
-    "".to_string()
+/// How many levels of sub-regions are rolled up into each region; all of them
+/// when absent, none with 0.
+#[derive(Deserialize, Debug, Default)]
+pub struct DepthParams {
+    depth: Option<i32>,
 }
 
-#[derive(Serialize, FromRow, Default)]
-pub struct SumResponse {
-    region: String,
-    total: i64,
+impl DepthParams {
+    fn validate(&self) -> Result<Option<i32>, (StatusCode, String)> {
+        match self.depth {
+            Some(depth) if depth < 0 => Err((StatusCode::BAD_REQUEST, "depth must not be negative".to_string())),
+            depth => Ok(depth),
+        }
+    }
 }
 
-pub async fn sum_regions(State(state): State<d13::AppState>) -> impl IntoResponse
+pub async fn sum_regions(
+    State(state): State<d13::AppState>,
+    Query(params): Query<DepthParams>,
+    Query(export): Query<ExportParams>,
+) -> Result<Response, (StatusCode, String)>
 {
-    let totals = sqlx::query_as::<_, SumResponse>(
-        r#"
-            SELECT r.name AS region,
-                sum(o.quantity) AS total
-            FROM orders o
-                JOIN regions r
-                    ON o.region_id = r.id
-            GROUP BY r.name
-            ORDER BY r.name ASC
-        "#,
-    )
-        .fetch_all(&state.pool)
+    let depth = params.validate()?;
+    let totals = state.store.region_totals(depth)
         .await
-        .unwrap();
-
-    Json(totals)
-}
-
-
-
-#[derive(Serialize, FromRow, Default)]
-pub struct TopGift {
-    region: String,
-    top_gifts: Vec<String>,
-}
-
-pub async fn top_gifts(State(state): State<d13::AppState>, Path(limit): Path<i32>
-) -> impl IntoResponse {
-
-    let top_gifts = sqlx::query_as::<_, TopGift>(
-        r#"
-            SELECT r.name AS region,
-                array_remove(array_agg(o.gift_name), NULL) AS top_gifts
-            FROM regions r
-            LEFT JOIN LATERAL (
-                SELECT o.gift_name,
-                    sum(o.quantity) AS total_quantity
-                FROM orders o
-                WHERE o.region_id = r.id
-                GROUP BY o.gift_name
-                ORDER BY total_quantity DESC,
-                    o.gift_name ASC
-                LIMIT $1
-                ) o ON TRUE
-            GROUP BY r.name
-            ORDER BY r.name ASC
-        "#,
-    )
-        .bind(limit)
-        .fetch_all(&state.pool)
+        .map_err(orders::db_error)?;
+
+    csv_io::report("totals", &totals, export.format)
+}
+
+
+
+/// Ranking options for the top list; the defaults give the original report.
+#[derive(Deserialize, Debug, Default)]
+pub struct TopListParams {
+    #[serde(default)]
+    ranking: Ranking,
+    #[serde(default)]
+    metric: Metric,
+    /// Comma separated region ids, e.g. `1,2`.
+    regions: Option<String>,
+    /// Report each gift's rank, total and share instead of just its name.
+    #[serde(default)]
+    detail: bool,
+}
+
+#[derive(Serialize)]
+struct TopGiftRow<'a> {
+    region: &'a str,
+    rank: Option<i64>,
+    gift: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct TopGiftDetailRow<'a> {
+    region: &'a str,
+    region_total: i64,
+    rank: Option<i64>,
+    gift: Option<&'a str>,
+    total: Option<i64>,
+    share: Option<f64>,
+}
+
+impl<'a> TopGiftDetailRow<'a> {
+    fn new(top: &'a RegionTopGifts, gift: Option<&'a GiftRank>) -> Self {
+        Self {
+            region: &top.region,
+            region_total: top.total,
+            rank: gift.map(|gift| gift.rank),
+            gift: gift.map(|gift| gift.gift.as_str()),
+            total: gift.map(|gift| gift.total),
+            share: gift.map(|gift| gift.share),
+        }
+    }
+}
+
+pub async fn top_gifts(
+    State(state): State<d13::AppState>,
+    Path(limit): Path<i32>,
+    Query(depth): Query<DepthParams>,
+    Query(params): Query<TopListParams>,
+    Query(export): Query<ExportParams>,
+) -> Result<Response, (StatusCode, String)> {
+    if limit < 0 {
+        return Err((StatusCode::BAD_REQUEST, "number must not be negative".to_string()));
+    }
+    let regions = match params.regions {
+        None => None,
+        Some(list) => Some(list.split(',')
+            .filter(|id| !id.trim().is_empty())
+            .map(|id| id.trim().parse::<i32>()
+                .map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid region id {id}"))))
+            .collect::<Result<Vec<_>, _>>()?),
+    };
+    let query = TopGiftsQuery {
+        limit,
+        depth: depth.validate()?,
+        ranking: params.ranking,
+        metric: params.metric,
+        regions,
+    };
+
+    let top_gifts = state.store.top_gifts(&query)
         .await
-        .unwrap();
+        .map_err(orders::db_error)?;
 
-    Json(top_gifts)
+    match (export.format, params.detail) {
+        (Format::Json, true) => csv_io::report("top_list", &top_gifts, export.format),
+        (Format::Json, false) => {
+            let top_gifts = top_gifts.into_iter().map(TopGifts::from).collect::<Vec<_>>();
+            csv_io::report("top_list", &top_gifts, export.format)
+        }
+        // CSV has no lists, so each gift gets its own row; regions without
+        // gifts keep one row with empty rank and gift.
+        (Format::Csv, false) => {
+            let rows = top_gifts.iter()
+                .flat_map(|top| {
+                    let gifts = top.top_gifts.iter()
+                        .map(|gift| TopGiftRow { region: &top.region, rank: Some(gift.rank), gift: Some(&gift.gift) });
+                    let empty = top.top_gifts.is_empty()
+                        .then_some(TopGiftRow { region: &top.region, rank: None, gift: None });
+                    gifts.chain(empty)
+                })
+                .collect::<Vec<_>>();
+            csv_io::report("top_list", &rows, export.format)
+        }
+        (Format::Csv, true) => {
+            let rows = top_gifts.iter()
+                .flat_map(|top| {
+                    let gifts = top.top_gifts.iter().map(|gift| TopGiftDetailRow::new(top, Some(gift)));
+                    let empty = top.top_gifts.is_empty().then(|| TopGiftDetailRow::new(top, None));
+                    gifts.chain(empty)
+                })
+                .collect::<Vec<_>>();
+            csv_io::report("top_list", &rows, export.format)
+        }
+    }
 }
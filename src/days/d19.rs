@@ -1,22 +1,31 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use chrono::{DateTime, Utc};
 use futures::{
-    stream::SplitSink,
+    stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
 };
 use axum::{
     Router,
     routing::{get, post},
-    extract::ws::{Message, WebSocket, WebSocketUpgrade},
-    extract::{State, Path},
+    extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+    extract::{State, Path, Query},
     http::StatusCode,
+    Json,
 };
 use axum::response::IntoResponse;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, broadcast::error::RecvError, mpsc, RwLock};
 use serde::{Deserialize, Serialize};
+use crate::days::chat::{ChatConfig, ChatHistory, Tweet, MAX_PAGE};
+use crate::days::orders::db_error;
 
-pub fn get_routes() -> Router {
+pub fn get_routes(history: Arc<ChatHistory>, config: ChatConfig) -> Router {
 
-    let state = BirdAppState::new();
+    let state = BirdAppState::new(history, config);
 
     Router::new()
         .route("/19", get(StatusCode::OK))
@@ -24,6 +33,9 @@ pub fn get_routes() -> Router {
         .route("/19/reset", post(reset_views))
         .route("/19/views", get(get_views))
         .route("/19/ws/room/:room/user/:user", get(ws_room))
+        .route("/19/rooms", get(list_rooms))
+        .route("/19/rooms/:room/users", get(room_users))
+        .route("/19/rooms/:room/history", get(room_history))
         .with_state(state)
 }
 
@@ -59,92 +71,581 @@ async fn handle_socket(mut socket: WebSocket) {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+/// Frames buffered per room. A connection that falls further behind is
+/// disconnected rather than slowing down everyone else.
+const ROOM_BUFFER: usize = 1024;
+
+/// Frames queued for a single connection, with the same rule when full.
+const DIRECT_BUFFER: usize = 64;
+
+/// Longest timed mute, 30 days; leave out `seconds` to mute until unmuted.
+const MAX_MUTE_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+/// What a connection writes to its socket. Text is serialized once, however
+/// many connections it goes to.
+#[derive(Clone)]
+enum Frame {
+    /// Counts as a view for every connection it is written to.
+    Tweet { id: i64, text: Arc<str> },
+    /// Only written to connections that asked for presence events.
+    Presence(Arc<str>),
+    /// Any other event: direct messages, mentions, topics and errors.
+    Notice(Arc<str>),
+    /// Close the connection with this reason.
+    Close(&'static str),
+}
+
+impl Frame {
+    fn event(event: &Event) -> Self {
+        let text = serde_json::to_string(event).unwrap().into();
+        match event {
+            Event::Join { .. } | Event::Leave { .. } => Frame::Presence(text),
+            _ => Frame::Notice(text),
+        }
+    }
+}
+
+/// One websocket joined to a room.
+struct Member {
+    user: String,
+    /// Frames for this connection alone.
+    direct: mpsc::Sender<Frame>,
+    /// Joined with the room key or an admin key.
+    moderator: bool,
+}
+
+/// The connections of one room share a broadcast channel; each has its own
+/// writer task draining it, and its direct queue, into the socket.
+///
+/// A room and its moderation state last while anyone is connected. User
+/// names are not authenticated, so moderation goes by key instead: whoever
+/// opens a room may set its key, and joining with that key or one of the
+/// configured admin keys grants moderation.
+struct Room {
+    frames: broadcast::Sender<Frame>,
+    /// Connection id to member.
+    members: BTreeMap<u64, Member>,
+    /// Set by the connection that opened the room, if it passed one.
+    key: Option<String>,
+    topic: Option<String>,
+    /// Muted users, until the given time or until unmuted.
+    muted: HashMap<String, Option<Instant>>,
+}
+
+impl Room {
+    fn new(key: Option<String>) -> Self {
+        let (frames, _) = broadcast::channel(ROOM_BUFFER);
+        Self {
+            frames,
+            members: BTreeMap::new(),
+            key,
+            topic: None,
+            muted: HashMap::new(),
+        }
+    }
+
+    /// Whether some connection of `user` moderates the room.
+    fn is_moderator(&self, user: &str) -> bool {
+        self.members.values().any(|member| member.user == user && member.moderator)
+    }
+
+    fn is_muted(&self, user: &str) -> bool {
+        match self.muted.get(user) {
+            Some(Some(until)) => Instant::now() < *until,
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Queue `frame` for connection `id`. A connection with a full queue is
+    /// removed, which closes it.
+    fn send_direct(&mut self, id: u64, frame: Frame) {
+        if let Some(member) = self.members.get(&id) {
+            if member.direct.try_send(frame).is_err() {
+                self.members.remove(&id);
+            }
+        }
+    }
+
+    /// Queue `frame` for every connection of `user`, returning how many there are.
+    fn send_user(&mut self, user: &str, frame: &Frame) -> usize {
+        let ids = self.connections_of(user);
+        for &id in &ids {
+            self.send_direct(id, frame.clone());
+        }
+        ids.len()
+    }
+
+    fn connections_of(&self, user: &str) -> Vec<u64> {
+        self.members
+            .iter()
+            .filter(|(_, member)| member.user == user)
+            .map(|(&id, _)| id)
+            .collect()
+    }
+}
+
+#[derive(Clone)]
 struct BirdAppState {
-    views: Arc<RwLock<u32>>,
-    rooms: Arc<RwLock<HashMap<u32, Vec<SplitSink<WebSocket, Message>>>>>
+    views: Arc<AtomicU32>,
+    rooms: Arc<RwLock<HashMap<u32, Room>>>,
+    next_connection: Arc<AtomicU64>,
+    history: Arc<ChatHistory>,
+    config: Arc<ChatConfig>,
 }
 
 impl BirdAppState {
-    fn new() -> Self {
+    fn new(history: Arc<ChatHistory>, config: ChatConfig) -> Self {
         Self {
-            views: Arc::new(RwLock::new(0)),
+            views: Arc::new(AtomicU32::new(0)),
             rooms: Arc::new(RwLock::new(HashMap::new())),
+            next_connection: Arc::new(AtomicU64::new(1)),
+            history,
+            config: Arc::new(config),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-struct Tweet {
+/// Typed client messages, selected by `type`. A bare `{"message": ...}`
+/// without a `type` is still a tweet, silently dropped when rejected as it
+/// always was; typed messages get an error event back instead.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Command {
+    Tweet { message: String },
+    /// Private message to every connection of `to` in the same room.
+    Direct { to: String, message: String },
+    // Moderation, for connections that joined with a key.
+    Kick { user: String },
+    /// Indefinitely unless `seconds` is given.
+    Mute { user: String, seconds: Option<u64> },
+    Unmute { user: String },
+    /// A missing topic clears it.
+    Topic { topic: Option<String> },
+}
+
+/// Server events other than tweets, tagged by `event`.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    /// Presence events, sent to connections that joined with `presence=true`.
+    Join { room: u32, user: &'a str, timestamp: DateTime<Utc> },
+    Leave { room: u32, user: &'a str, timestamp: DateTime<Utc> },
+    Direct { room: u32, from: &'a str, to: &'a str, message: &'a str, timestamp: DateTime<Utc> },
+    /// A tweet mentioning the recipient as `@user`.
+    Mention { room: u32, tweet: &'a Tweet },
+    Topic { room: u32, topic: Option<&'a str>, by: &'a str },
+    Kicked { room: u32, by: &'a str },
+    Muted {
+        room: u32,
+        by: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        until: Option<DateTime<Utc>>,
+    },
+    Unmuted { room: u32, by: &'a str },
+    /// The client's last message was rejected.
+    Error { reason: &'a str },
+}
+
+#[derive(Serialize)]
+struct RoomInfo {
+    room: u32,
+    connections: usize,
     #[serde(skip_serializing_if = "Option::is_none")]
-    user: Option<String>,
-    message: String,
+    topic: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JoinParams {
+    /// Number of recent messages to send on join. Replayed messages do not
+    /// count as views.
+    #[serde(default)]
+    history: usize,
+    /// Also receive presence events. Off by default so plain clients only
+    /// ever see tweets.
+    #[serde(default)]
+    presence: bool,
+    /// Opening a room with a key sets it; joining with the room's key or an
+    /// admin key allows moderation.
+    key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HistoryParams {
+    /// Only messages with a smaller id, i.e. the page before this one.
+    before: Option<i64>,
+    #[serde(default = "default_page_size")]
+    limit: usize,
+}
+
+fn default_page_size() -> usize {
+    50
+}
+
+#[derive(Serialize)]
+struct HistoryPage {
+    messages: Vec<Tweet>,
+    /// Pass as `before` to get the page of older messages; absent once there
+    /// is nothing older.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_before: Option<i64>,
 }
 
 async fn reset_views(State(state): State<BirdAppState>) {
-    *state.views.write().await = 0;
+    state.views.store(0, Ordering::Relaxed);
 }
 
 async fn get_views(State(state): State<BirdAppState>) -> impl IntoResponse {
-    state.views.read().await.to_string()
+    state.views.load(Ordering::Relaxed).to_string()
+}
+
+async fn room_history(
+    State(state): State<BirdAppState>,
+    Path(room): Path<u32>,
+    Query(params): Query<HistoryParams>,
+) -> Result<Json<HistoryPage>, (StatusCode, String)> {
+    let messages = state.history.page(room, params.before, params.limit).await.map_err(db_error)?;
+    // A short page is the oldest one.
+    let next_before = if messages.len() == params.limit.min(MAX_PAGE) && params.limit > 0 {
+        messages.first().and_then(|tweet| tweet.id)
+    } else {
+        None
+    };
+    Ok(Json(HistoryPage { messages, next_before }))
+}
+
+async fn list_rooms(State(state): State<BirdAppState>) -> Json<Vec<RoomInfo>> {
+    let rooms = state.rooms.read().await;
+    let mut list: Vec<RoomInfo> = rooms
+        .iter()
+        .map(|(&room, state)| RoomInfo {
+            room,
+            connections: state.members.len(),
+            topic: state.topic.clone(),
+        })
+        .collect();
+    list.sort_by_key(|info| info.room);
+    Json(list)
+}
+
+/// Distinct users in `room`, sorted; a user can be connected more than once.
+async fn room_users(
+    State(state): State<BirdAppState>,
+    Path(room): Path<u32>,
+) -> Result<Json<Vec<String>>, StatusCode> {
+    let rooms = state.rooms.read().await;
+    let state = rooms.get(&room).ok_or(StatusCode::NOT_FOUND)?;
+    let users: BTreeSet<&String> = state.members.values().map(|member| &member.user).collect();
+    Ok(Json(users.into_iter().cloned().collect()))
 }
 
 async fn ws_room(
     ws: WebSocketUpgrade,
     State(state): State<BirdAppState>,
     Path((room, user)): Path<(u32, String)>,
+    Query(params): Query<JoinParams>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_room(socket, state, room, user))
+    ws.on_upgrade(move |socket| handle_room(socket, state, room, user, params))
 }
 
-async fn handle_room(ws: WebSocket, state: BirdAppState, room: u32, user: String) {
+async fn handle_room(ws: WebSocket, state: BirdAppState, room: u32, user: String, params: JoinParams) {
 
-    let (sender, mut receiver) = ws.split();
+    let (sender, receiver) = ws.split();
+    let id = state.next_connection.fetch_add(1, Ordering::Relaxed);
+    let (direct, direct_frames) = mpsc::channel(DIRECT_BUFFER);
 
+    // Announce before subscribing, so the joining connection does not see itself.
     let mut rooms = state.rooms.write().await;
-    if let Some(senders) = rooms.get_mut(&room) {
-        senders.push(sender);
-    } else {
-        rooms.insert(room, vec![sender]);
-    }
+    let joined = rooms.entry(room).or_insert_with(|| Room::new(params.key.clone()));
+    let moderator = params.key.as_ref().is_some_and(|key| {
+        joined.key.as_ref() == Some(key) || state.config.admin_keys.contains(key)
+    });
+    let _ = joined.frames.send(Frame::event(&Event::Join { room, user: &user, timestamp: Utc::now() }));
+    let frames = joined.frames.subscribe();
+    let publish = joined.frames.clone();
+    joined.members.insert(id, Member { user: user.clone(), direct, moderator });
     drop(rooms);
 
-    while let Some(msg) = receiver.next().await {
-        let msg = if let Ok(msg) = msg {
-            msg
+    // Anything sent after subscribing is also in the replay; the writer skips it by id.
+    let replay = state.history.recent(room, params.history).await;
+    let mut writer = tokio::spawn(write_frames(
+        sender,
+        frames,
+        direct_frames,
+        replay,
+        params.presence,
+        state.views.clone(),
+    ));
+
+    // Whichever half stops first ends the connection.
+    let session = Session { state: &state, room, id, user: &user, publish: &publish };
+    tokio::select! {
+        _ = session.read(receiver) => writer.abort(),
+        _ = &mut writer => (),
+    }
+
+    let mut rooms = state.rooms.write().await;
+    if let Some(left) = rooms.get_mut(&room) {
+        left.members.remove(&id);
+        if left.members.is_empty() {
+            rooms.remove(&room);
         } else {
-            // client disconnected
-            return;
-        };
-        //println!("RECV MSG: {:?}", msg);
-        match msg {
-            Message::Text(tweet) => {
-                if let Ok(mut tweet) = serde_json::from_str::<Tweet>(&tweet) {
+            let _ = left.frames.send(Frame::event(&Event::Leave { room, user: &user, timestamp: Utc::now() }));
+        }
+    }
+}
+
+/// The reading half of one connection.
+struct Session<'a> {
+    state: &'a BirdAppState,
+    room: u32,
+    id: u64,
+    user: &'a str,
+    publish: &'a broadcast::Sender<Frame>,
+}
+
+impl Session<'_> {
+    /// Handle client messages until the connection closes.
+    async fn read(&self, mut receiver: SplitStream<WebSocket>) {
+        while let Some(Ok(msg)) = receiver.next().await {
+            let text = match msg {
+                Message::Text(text) => text,
+                // client disconnected
+                Message::Close(_) => return,
+                _ => continue,
+            };
+            let Ok(value) = serde_json::from_str::<serde_json::Value>(&text) else {
+                continue;
+            };
+            if value.get("type").is_none() {
+                if let Ok(tweet) = serde_json::from_value::<Tweet>(value) {
                     if tweet.user.is_none() {
-                        if tweet.message.len() <= 128 {
-                            tweet.user = Some(user.clone());
-
-                            let message = Message::Text(serde_json::to_string(&tweet).unwrap());
-
-                            let mut rooms = state.rooms.write().await;
-                            if let Some(senders) = rooms.get_mut(&room) {
-                                for sender in senders {
-                                    if sender.send(message.clone()).await.is_ok() {
-                                        // println!("SEND MSG: {:?}", message);
-                                        *state.views.write().await += 1;
-                                        // if error, client disconnected
-                                    }
-                                }
-                            }
-                        }
+                        let _ = self.tweet(tweet.message).await;
                     }
                 }
+                continue;
             }
-            Message::Close(_) => {
-                // client disconnected
+            let result = match serde_json::from_value::<Command>(value) {
+                Ok(command) => self.run(command).await,
+                Err(e) => Err(format!("invalid message: {e}")),
+            };
+            if let Err(reason) = result {
+                self.reply(&Event::Error { reason: &reason }).await;
+            }
+        }
+    }
+
+    async fn run(&self, command: Command) -> Result<(), String> {
+        match command {
+            Command::Tweet { message } => self.tweet(message).await,
+            Command::Direct { to, message } => self.direct(&to, &message).await,
+            Command::Kick { user } => self.kick(&user).await,
+            Command::Mute { user, seconds } => self.mute(&user, seconds).await,
+            Command::Unmute { user } => self.unmute(&user).await,
+            Command::Topic { topic } => self.topic(topic).await,
+        }
+    }
+
+    /// Queue `event` for this connection only.
+    async fn reply(&self, event: &Event<'_>) {
+        if let Some(room) = self.state.rooms.write().await.get_mut(&self.room) {
+            room.send_direct(self.id, Frame::event(event));
+        }
+    }
+
+    /// Reject the message if the user is muted or it fails a content filter.
+    async fn check(&self, message: &str) -> Result<(), String> {
+        let muted = self.state.rooms.read().await
+            .get(&self.room)
+            .is_some_and(|room| room.is_muted(self.user));
+        if muted {
+            return Err("you are muted".to_string());
+        }
+        match self.state.config.rejects(message) {
+            Some(reason) => Err(reason.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    async fn tweet(&self, message: String) -> Result<(), String> {
+        self.check(&message).await?;
+
+        let tweet = Tweet { id: None, user: Some(self.user.to_string()), message, timestamp: None };
+        let tweet = self.state.history.record(self.room, tweet, |tweet| {
+            let text = serde_json::to_string(tweet).unwrap().into();
+            // Fails only when nobody is subscribed.
+            let _ = self.publish.send(Frame::Tweet { id: tweet.id.unwrap_or_default(), text });
+        }).await;
+
+        let mentioned: BTreeSet<&str> = mentions(&tweet.message).filter(|&name| name != self.user).collect();
+        if !mentioned.is_empty() {
+            let frame = Frame::event(&Event::Mention { room: self.room, tweet: &tweet });
+            if let Some(room) = self.state.rooms.write().await.get_mut(&self.room) {
+                for name in mentioned {
+                    room.send_user(name, &frame);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Deliver to the recipient, and echo to the sender's connections.
+    async fn direct(&self, to: &str, message: &str) -> Result<(), String> {
+        self.check(message).await?;
+
+        let event = Event::Direct { room: self.room, from: self.user, to, message, timestamp: Utc::now() };
+        let frame = Frame::event(&event);
+        let mut rooms = self.state.rooms.write().await;
+        let room = rooms.get_mut(&self.room).ok_or("not in a room")?;
+        if room.send_user(to, &frame) == 0 {
+            return Err(format!("{to} is not in this room"));
+        }
+        if to != self.user {
+            room.send_user(self.user, &frame);
+        }
+        Ok(())
+    }
+
+    /// The room, if this connection may moderate it.
+    fn moderated<'r>(&self, rooms: &'r mut HashMap<u32, Room>) -> Result<&'r mut Room, String> {
+        let room = rooms.get_mut(&self.room).ok_or("not in a room")?;
+        if !room.members.get(&self.id).is_some_and(|member| member.moderator) {
+            return Err("only moderators can do that".to_string());
+        }
+        Ok(room)
+    }
+
+    /// Close every connection of `user`; they may rejoin.
+    async fn kick(&self, user: &str) -> Result<(), String> {
+        let mut rooms = self.state.rooms.write().await;
+        let room = self.moderated(&mut rooms)?;
+        if room.is_moderator(user) {
+            return Err("moderators cannot be kicked".to_string());
+        }
+        let ids = room.connections_of(user);
+        if ids.is_empty() {
+            return Err(format!("{user} is not in this room"));
+        }
+        let kicked = Frame::event(&Event::Kicked { room: self.room, by: self.user });
+        for id in ids {
+            room.send_direct(id, kicked.clone());
+            room.send_direct(id, Frame::Close("kicked"));
+            // Dropping the queue ends the writer even if the frames did not fit.
+            room.members.remove(&id);
+        }
+        Ok(())
+    }
+
+    async fn mute(&self, user: &str, seconds: Option<u64>) -> Result<(), String> {
+        // Expiry as a monotonic deadline and as the time shown to the user.
+        let expiry = match seconds {
+            Some(seconds) if seconds > MAX_MUTE_SECONDS => {
+                return Err(format!("mutes last at most {MAX_MUTE_SECONDS} seconds"));
+            }
+            Some(seconds) => {
+                let duration = Duration::from_secs(seconds);
+                let deadline = Instant::now().checked_add(duration);
+                let until = chrono::Duration::from_std(duration)
+                    .ok()
+                    .and_then(|duration| Utc::now().checked_add_signed(duration));
+                Some(deadline.zip(until).ok_or("mute too long")?)
+            }
+            None => None,
+        };
+
+        let mut rooms = self.state.rooms.write().await;
+        let room = self.moderated(&mut rooms)?;
+        if room.is_moderator(user) {
+            return Err("moderators cannot be muted".to_string());
+        }
+        room.muted.insert(user.to_string(), expiry.map(|(deadline, _)| deadline));
+        let until = expiry.map(|(_, until)| until);
+        room.send_user(user, &Frame::event(&Event::Muted { room: self.room, by: self.user, until }));
+        Ok(())
+    }
+
+    async fn unmute(&self, user: &str) -> Result<(), String> {
+        let mut rooms = self.state.rooms.write().await;
+        let room = self.moderated(&mut rooms)?;
+        if room.muted.remove(user).is_none() {
+            return Err(format!("{user} is not muted"));
+        }
+        room.send_user(user, &Frame::event(&Event::Unmuted { room: self.room, by: self.user }));
+        Ok(())
+    }
+
+    /// Set or clear the topic and announce it to the whole room. Topics pass
+    /// the same content filters as messages.
+    async fn topic(&self, topic: Option<String>) -> Result<(), String> {
+        if let Some(reason) = topic.as_deref().and_then(|topic| self.state.config.rejects(topic)) {
+            return Err(reason.to_string());
+        }
+        let mut rooms = self.state.rooms.write().await;
+        let room = self.moderated(&mut rooms)?;
+        let event = Event::Topic { room: self.room, topic: topic.as_deref(), by: self.user };
+        let _ = room.frames.send(Frame::event(&event));
+        room.topic = topic;
+        Ok(())
+    }
+}
+
+/// Users mentioned as `@user`, ignoring trailing punctuation.
+fn mentions(message: &str) -> impl Iterator<Item = &str> {
+    message
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|name| name.trim_end_matches(|c: char| c.is_ascii_punctuation() && c != '_' && c != '-'))
+        .filter(|name| !name.is_empty())
+}
+
+/// Drain the room and the connection's own queue into its socket, counting a
+/// view per tweet written. Falling more than `ROOM_BUFFER` frames behind, or
+/// being removed from the room, closes the connection.
+async fn write_frames(
+    mut sender: SplitSink<WebSocket, Message>,
+    mut frames: broadcast::Receiver<Frame>,
+    mut direct: mpsc::Receiver<Frame>,
+    replay: Vec<Tweet>,
+    presence: bool,
+    views: Arc<AtomicU32>,
+) {
+    let mut replayed = 0;
+    for tweet in replay {
+        replayed = tweet.id.unwrap_or_default();
+        if sender.send(Message::Text(serde_json::to_string(&tweet).unwrap())).await.is_err() {
+            // client disconnected
+            return;
+        }
+    }
+
+    loop {
+        let frame = tokio::select! {
+            biased;
+            frame = direct.recv() => frame.unwrap_or(Frame::Close("too slow")),
+            frame = frames.recv() => match frame {
+                Ok(frame) => frame,
+                Err(RecvError::Lagged(_)) => Frame::Close("too slow"),
+                Err(RecvError::Closed) => return,
+            },
+        };
+        let (text, view) = match frame {
+            Frame::Tweet { id, .. } if id <= replayed => continue,
+            Frame::Tweet { text, .. } => (text, true),
+            Frame::Presence(text) if presence => (text, false),
+            Frame::Presence(_) => continue,
+            Frame::Notice(text) => (text, false),
+            Frame::Close(reason) => {
+                let close = CloseFrame { code: close_code::POLICY, reason: reason.into() };
+                let _ = sender.send(Message::Close(Some(close))).await;
                 return;
             }
-            _ => (),
         };
+        if sender.send(Message::Text(text.to_string())).await.is_err() {
+            // client disconnected
+            return;
+        }
+        if view {
+            views.fetch_add(1, Ordering::Relaxed);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,517 @@
+use std::io::Cursor;
+use image::{
+    imageops::FilterType,
+    io::{Limits, Reader},
+    DynamicImage,
+    GenericImageView,
+    ImageFormat,
+    ImageOutputFormat,
+    Rgba,
+    RgbaImage,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Decode any format supported by the `image` crate, guessing it from the content.
+pub fn decode(data: &[u8]) -> Result<(DynamicImage, Option<ImageFormat>), String> {
+    decode_with_limits(data, Limits::default())
+}
+
+pub fn decode_with_limits(data: &[u8], limits: Limits) -> Result<(DynamicImage, Option<ImageFormat>), String> {
+    let mut reader = Reader::new(Cursor::new(data))
+        .with_guessed_format()
+        .expect("This will never fail using Cursor");
+    reader.limits(limits);
+    let format = reader.format();
+    let img = reader.decode().map_err(|e| e.to_string())?;
+
+    Ok((img, format))
+}
+
+/// The original "magic red" rule: red is brighter than green and blue combined.
+pub fn is_magic_red(pixel: &Rgba<u8>) -> bool {
+    let [r, g, b, _] = pixel.0;
+    r as u16 > (g as u16 + b as u16)
+}
+
+pub fn count_red_pixels(img: &DynamicImage) -> u32 {
+    img.pixels()
+        .filter(|x| is_magic_red(&x.2))
+        .count() as u32
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+}
+
+/// A named pixel test, counted for every analyzed image.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Predicate {
+    /// The channel value exceeds the sum of the other two by more than `threshold`.
+    Dominance {
+        name: String,
+        channel: Channel,
+        #[serde(default)]
+        threshold: i32,
+    },
+    /// Hue in degrees, saturation and value in `0.0..=1.0`, all bounds inclusive.
+    /// A hue range with `from > to` wraps around 360 (e.g. reds: `[330, 30]`).
+    Hsv {
+        name: String,
+        hue: (f32, f32),
+        #[serde(default = "full_range")]
+        saturation: (f32, f32),
+        #[serde(default = "full_range")]
+        value: (f32, f32),
+    },
+}
+
+fn full_range() -> (f32, f32) {
+    (0.0, 1.0)
+}
+
+impl Predicate {
+    fn name(&self) -> &str {
+        match self {
+            Predicate::Dominance { name, .. } => name,
+            Predicate::Hsv { name, .. } => name,
+        }
+    }
+
+    fn matches(&self, pixel: &Rgba<u8>) -> bool {
+        let [r, g, b, _] = pixel.0;
+        let (r, g, b) = (r as i32, g as i32, b as i32);
+        match self {
+            Predicate::Dominance { channel, threshold, .. } => {
+                let (main, others) = match channel {
+                    Channel::Red => (r, g + b),
+                    Channel::Green => (g, r + b),
+                    Channel::Blue => (b, r + g),
+                };
+                main - others > *threshold
+            }
+            Predicate::Hsv { hue, saturation, value, .. } => {
+                let (h, s, v) = to_hsv(pixel);
+                let hue_ok = if hue.0 <= hue.1 {
+                    h >= hue.0 && h <= hue.1
+                } else {
+                    h >= hue.0 || h <= hue.1
+                };
+                hue_ok
+                    && s >= saturation.0 && s <= saturation.1
+                    && v >= value.0 && v <= value.1
+            }
+        }
+    }
+}
+
+fn default_predicates() -> Vec<Predicate> {
+    [("red", Channel::Red), ("green", Channel::Green), ("blue", Channel::Blue)]
+        .into_iter()
+        .map(|(name, channel)| Predicate::Dominance { name: name.to_string(), channel, threshold: 0 })
+        .collect()
+}
+
+/// Hue in degrees, saturation and value in `0.0..=1.0`.
+pub fn to_hsv(pixel: &Rgba<u8>) -> (f32, f32, f32) {
+    let [r, g, b, _] = pixel.0;
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let h = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let s = if max == 0.0 { 0.0 } else { delta / max };
+
+    (h, s, max)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AnalysisOptions {
+    /// Number of dominant colors to extract, at most `MAX_CLUSTERS`.
+    #[serde(default = "default_clusters", deserialize_with = "clusters")]
+    pub clusters: usize,
+    #[serde(default = "default_predicates")]
+    pub predicates: Vec<Predicate>,
+}
+
+/// k-means runs over every sample for every cluster, so k stays small.
+const MAX_CLUSTERS: usize = 32;
+
+fn default_clusters() -> usize {
+    5
+}
+
+fn clusters<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    let clusters = usize::deserialize(deserializer)?;
+    if clusters > MAX_CLUSTERS {
+        return Err(serde::de::Error::custom(format!("at most {MAX_CLUSTERS} clusters are allowed")));
+    }
+    Ok(clusters)
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        Self {
+            clusters: default_clusters(),
+            predicates: default_predicates(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct DominantColor {
+    hex: String,
+    rgb: [u8; 3],
+    share: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Histograms {
+    red: Vec<u32>,
+    green: Vec<u32>,
+    blue: Vec<u32>,
+    alpha: Vec<u32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PredicateCount {
+    name: String,
+    count: u32,
+    ratio: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Analysis {
+    format: Option<String>,
+    mime_type: Option<String>,
+    color_type: String,
+    width: u32,
+    height: u32,
+    pixels: u64,
+    transparency: f64,
+    dominant_colors: Vec<DominantColor>,
+    histograms: Histograms,
+    predicates: Vec<PredicateCount>,
+}
+
+pub fn analyze(img: &DynamicImage, format: Option<ImageFormat>, options: &AnalysisOptions) -> Analysis {
+    let (width, height) = img.dimensions();
+    let pixels = width as u64 * height as u64;
+
+    let mut histograms = Histograms {
+        red: vec![0; 256],
+        green: vec![0; 256],
+        blue: vec![0; 256],
+        alpha: vec![0; 256],
+    };
+    let mut transparent = 0u64;
+    let mut counts = vec![0u32; options.predicates.len()];
+
+    for (_, _, pixel) in img.pixels() {
+        let [r, g, b, a] = pixel.0;
+        histograms.red[r as usize] += 1;
+        histograms.green[g as usize] += 1;
+        histograms.blue[b as usize] += 1;
+        histograms.alpha[a as usize] += 1;
+        if a < u8::MAX {
+            transparent += 1;
+        }
+        for (count, predicate) in counts.iter_mut().zip(&options.predicates) {
+            if predicate.matches(&pixel) {
+                *count += 1;
+            }
+        }
+    }
+
+    let ratio = |n: u64| if pixels == 0 { 0.0 } else { n as f64 / pixels as f64 };
+
+    Analysis {
+        format: format.map(|f| f.extensions_str()[0].to_string()),
+        mime_type: format.map(|f| f.to_mime_type().to_string()),
+        color_type: format!("{:?}", img.color()),
+        width,
+        height,
+        pixels,
+        transparency: ratio(transparent),
+        dominant_colors: dominant_colors(img, options.clusters),
+        histograms,
+        predicates: options.predicates.iter()
+            .zip(counts)
+            .map(|(predicate, count)| PredicateCount {
+                name: predicate.name().to_string(),
+                count,
+                ratio: ratio(count as u64),
+            })
+            .collect(),
+    }
+}
+
+/// Upper bound of pixels fed to k-means; larger images are sampled with a fixed stride.
+const MAX_SAMPLES: usize = 16_384;
+const MAX_ITERATIONS: usize = 20;
+
+/// Dominant colors by k-means clustering over (sampled) opaque-ish pixels, largest cluster first.
+pub fn dominant_colors(img: &DynamicImage, k: usize) -> Vec<DominantColor> {
+    let total = img.width() as usize * img.height() as usize;
+    let step = (total / MAX_SAMPLES).max(1);
+    let samples = img.pixels()
+        .step_by(step)
+        .filter(|(_, _, p)| p.0[3] > 0)
+        .map(|(_, _, p)| [p.0[0] as f32, p.0[1] as f32, p.0[2] as f32])
+        .collect::<Vec<_>>();
+
+    if samples.is_empty() || k == 0 {
+        return vec![];
+    }
+
+    // Deterministic seeding: spread the initial centroids over the samples sorted by luminance.
+    let mut sorted = samples.clone();
+    sorted.sort_by(|a, b| luminance(a).total_cmp(&luminance(b)));
+    let k = k.min(sorted.len());
+    let mut centroids = (0..k)
+        .map(|i| sorted[(2 * i + 1) * sorted.len() / (2 * k)])
+        .collect::<Vec<_>>();
+
+    let mut assignments = vec![0usize; samples.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for (sample, assigned) in samples.iter().zip(assignments.iter_mut()) {
+            let nearest = nearest(&centroids, sample);
+            if nearest != *assigned {
+                *assigned = nearest;
+                changed = true;
+            }
+        }
+
+        let mut sums = vec![[0f32; 3]; k];
+        let mut sizes = vec![0usize; k];
+        for (sample, &assigned) in samples.iter().zip(&assignments) {
+            for c in 0..3 {
+                sums[assigned][c] += sample[c];
+            }
+            sizes[assigned] += 1;
+        }
+        for (i, centroid) in centroids.iter_mut().enumerate() {
+            if sizes[i] > 0 {
+                *centroid = sums[i].map(|s| s / sizes[i] as f32);
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    let mut sizes = vec![0usize; k];
+    for &assigned in &assignments {
+        sizes[assigned] += 1;
+    }
+
+    let mut colors = centroids.into_iter()
+        .zip(sizes)
+        .filter(|(_, size)| *size > 0)
+        .map(|(centroid, size)| {
+            let rgb = centroid.map(|c| c.round().clamp(0.0, 255.0) as u8);
+            DominantColor {
+                hex: format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2]),
+                rgb,
+                share: size as f64 / samples.len() as f64,
+            }
+        })
+        .collect::<Vec<_>>();
+    colors.sort_by(|a, b| b.share.total_cmp(&a.share));
+    colors
+}
+
+fn luminance(c: &[f32; 3]) -> f32 {
+    0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2]
+}
+
+fn nearest(centroids: &[[f32; 3]], sample: &[f32; 3]) -> usize {
+    centroids.iter()
+        .map(|c| (0..3).map(|i| (c[i] - sample[i]).powi(2)).sum::<f32>())
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+/// A single step of a transformation pipeline, e.g. `{"op": "resize", "width": 64, "height": 64}`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    /// Fit inside `width` x `height` keeping the aspect ratio, unless `exact` is set.
+    Resize {
+        width: u32,
+        height: u32,
+        #[serde(default)]
+        exact: bool,
+    },
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Clockwise, in multiples of 90 degrees.
+    Rotate {
+        degrees: u32,
+    },
+    Grayscale,
+    Blur {
+        sigma: f32,
+    },
+    /// Magic red pixels become white, everything else black.
+    RedMask,
+}
+
+impl Operation {
+    /// Size of the result for a `width` x `height` input, computed without
+    /// touching any pixels so oversized targets are rejected up front.
+    pub fn output_dimensions(&self, (width, height): (u32, u32)) -> (u64, u64) {
+        match *self {
+            Operation::Resize { width: target_width, height: target_height, exact: true } =>
+                (target_width as u64, target_height as u64),
+            Operation::Resize { width: target_width, height: target_height, exact: false } => {
+                // Same fit as `DynamicImage::resize`.
+                let ratio = f64::min(
+                    target_width as f64 / width as f64,
+                    target_height as f64 / height as f64,
+                );
+                let fit = |side: u32| ((side as f64 * ratio).round() as u64).max(1);
+                (fit(width), fit(height))
+            }
+            Operation::Crop { width: crop_width, height: crop_height, .. } => (crop_width as u64, crop_height as u64),
+            Operation::Rotate { degrees } if degrees % 180 == 90 => (height as u64, width as u64),
+            _ => (width as u64, height as u64),
+        }
+    }
+
+    pub fn apply(&self, img: DynamicImage) -> Result<DynamicImage, String> {
+        match *self {
+            Operation::Resize { width, height, exact } => {
+                if width == 0 || height == 0 {
+                    return Err("resize: width and height must be positive".to_string());
+                }
+                Ok(if exact {
+                    img.resize_exact(width, height, FilterType::Lanczos3)
+                } else {
+                    img.resize(width, height, FilterType::Lanczos3)
+                })
+            }
+            Operation::Crop { x, y, width, height } => {
+                let (w, h) = img.dimensions();
+                if width == 0 || height == 0
+                    || x as u64 + width as u64 > w as u64
+                    || y as u64 + height as u64 > h as u64 {
+                    return Err(format!("crop: region out of bounds for a {w}x{h} image"));
+                }
+                Ok(img.crop_imm(x, y, width, height))
+            }
+            Operation::Rotate { degrees } => match degrees % 360 {
+                0 => Ok(img),
+                90 => Ok(img.rotate90()),
+                180 => Ok(img.rotate180()),
+                270 => Ok(img.rotate270()),
+                _ => Err(format!("rotate: {degrees} is not a multiple of 90")),
+            },
+            Operation::Grayscale => Ok(img.grayscale()),
+            Operation::Blur { sigma } => {
+                if !(sigma > 0.0 && sigma <= MAX_BLUR_SIGMA) {
+                    return Err(format!("blur: sigma must be in (0, {MAX_BLUR_SIGMA}]"));
+                }
+                Ok(img.blur(sigma))
+            }
+            Operation::RedMask => {
+                let (w, h) = img.dimensions();
+                let mask = RgbaImage::from_fn(w, h, |x, y| {
+                    if is_magic_red(&img.get_pixel(x, y)) {
+                        Rgba([u8::MAX, u8::MAX, u8::MAX, u8::MAX])
+                    } else {
+                        Rgba([0, 0, 0, u8::MAX])
+                    }
+                });
+                Ok(DynamicImage::ImageRgba8(mask))
+            }
+        }
+    }
+}
+
+const MAX_BLUR_SIGMA: f32 = 50.0;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Webp => "webp",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Webp => "image/webp",
+        }
+    }
+}
+
+/// Run `operations` in order, rejecting any intermediate image larger than
+/// `max_dimension` before it is produced.
+pub fn transform(
+    mut img: DynamicImage,
+    operations: &[Operation],
+    max_dimension: u32,
+) -> Result<DynamicImage, String> {
+    for op in operations {
+        let (w, h) = op.output_dimensions(img.dimensions());
+        if w > max_dimension as u64 || h > max_dimension as u64 {
+            return Err(format!("{w}x{h} exceeds the maximum of {max_dimension}x{max_dimension}"));
+        }
+        img = op.apply(img)?;
+    }
+    Ok(img)
+}
+
+/// Encode to `format`; `quality` (1-100) only applies to JPEG, WebP output is lossless.
+pub fn encode(img: &DynamicImage, format: OutputFormat, quality: u8) -> Result<Vec<u8>, String> {
+    let mut out = Cursor::new(Vec::new());
+    match format {
+        OutputFormat::Png => img.write_to(&mut out, ImageOutputFormat::Png)
+            .map_err(|e| e.to_string())?,
+        // JPEG has no alpha channel.
+        OutputFormat::Jpeg => DynamicImage::ImageRgb8(img.to_rgb8())
+            .write_to(&mut out, ImageOutputFormat::Jpeg(quality.clamp(1, 100)))
+            .map_err(|e| e.to_string())?,
+        OutputFormat::Webp => {
+            let rgba = img.to_rgba8();
+            image_webp::WebPEncoder::new(&mut out)
+                .encode(&rgba, rgba.width(), rgba.height(), image_webp::ColorType::Rgba8)
+                .map_err(|e| e.to_string())?
+        }
+    }
+    Ok(out.into_inner())
+}
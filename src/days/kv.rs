@@ -0,0 +1,220 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use shuttle_persist::{PersistError, PersistInstance};
+use tokio::sync::Mutex;
+use crate::days::namespace::Namespace;
+
+pub const MAX_VALUE_BYTES: usize = 64 * 1024;
+pub const MAX_KEYS: usize = 1000;
+pub const MAX_TOTAL_BYTES: usize = 8 * 1024 * 1024;
+
+/// Persisted envelope. The value is kept as JSON text because persist uses
+/// bincode, which cannot round-trip a `serde_json::Value`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Entry {
+    value: String,
+    version: u64,
+    updated_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl Entry {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Item {
+    pub key: String,
+    pub value: serde_json::Value,
+    pub version: u64,
+    pub updated_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct KeyInfo {
+    pub key: String,
+    pub version: u64,
+    pub size: usize,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+pub enum KvError {
+    NotFound,
+    /// Compare-and-swap failed; `actual` is 0 when the key does not exist.
+    VersionMismatch { expected: u64, actual: u64 },
+    ValueTooLarge(usize),
+    QuotaExceeded(String),
+    Persist(PersistError),
+}
+
+impl From<PersistError> for KvError {
+    fn from(e: PersistError) -> Self {
+        match e {
+            PersistError::Open(ref io) if io.kind() == std::io::ErrorKind::NotFound => KvError::NotFound,
+            e => KvError::Persist(e),
+        }
+    }
+}
+
+/// JSON key-value store on top of `PersistInstance`.
+///
+/// Versions start at 1 and grow with every write; passing `expected_version`
+/// turns a write into a compare-and-swap, with 0 meaning "must not exist".
+/// Expired entries are hidden right away and purged lazily on the next write.
+/// Entries that cannot be decoded are left out of listings and purged the
+/// same way, so a single bad entry cannot block the whole store.
+pub struct KvStore {
+    persist: PersistInstance,
+    // Serializes writes so version checks and quota checks are atomic.
+    write_lock: Mutex<()>,
+}
+
+impl KvStore {
+    pub fn new(persist: PersistInstance) -> Self {
+        Self { persist, write_lock: Mutex::new(()) }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Item, KvError> {
+        let entry = self.load_live(key, Utc::now())?.ok_or(KvError::NotFound)?;
+        Ok(Item {
+            key: key.to_string(),
+            value: serde_json::from_str(&entry.value).unwrap_or(serde_json::Value::Null),
+            version: entry.version,
+            updated_at: entry.updated_at,
+            expires_at: entry.expires_at,
+        })
+    }
+
+    pub async fn put(
+        &self,
+        key: &str,
+        value: &serde_json::Value,
+        ttl: Option<Duration>,
+        expected_version: Option<u64>,
+    ) -> Result<KeyInfo, KvError> {
+        let value = value.to_string();
+        if value.len() > MAX_VALUE_BYTES {
+            return Err(KvError::ValueTooLarge(value.len()));
+        }
+
+        let _guard = self.write_lock.lock().await;
+        let now = Utc::now();
+        let current = self.load_live(key, now)?;
+        let current_version = current.as_ref().map_or(0, |entry| entry.version);
+        check_version(expected_version, current_version)?;
+
+        let (keys, bytes) = self.usage(now)?;
+        let previous_size = current.as_ref().map_or(0, |entry| entry.value.len());
+        if current.is_none() && keys >= MAX_KEYS {
+            return Err(KvError::QuotaExceeded(format!("at most {MAX_KEYS} keys")));
+        }
+        if bytes - previous_size + value.len() > MAX_TOTAL_BYTES {
+            return Err(KvError::QuotaExceeded(format!("at most {MAX_TOTAL_BYTES} bytes in total")));
+        }
+
+        let entry = Entry {
+            value,
+            version: current_version + 1,
+            updated_at: now,
+            expires_at: ttl.map(|ttl| now + ttl),
+        };
+        self.persist.save(&storage_key(key), &entry)?;
+
+        Ok(key_info(key, &entry))
+    }
+
+    pub async fn delete(&self, key: &str, expected_version: Option<u64>) -> Result<(), KvError> {
+        let _guard = self.write_lock.lock().await;
+        let current = self.load_live(key, Utc::now())?.ok_or(KvError::NotFound)?;
+        check_version(expected_version, current.version)?;
+        self.persist.remove(&storage_key(key))?;
+        Ok(())
+    }
+
+    /// Live keys starting with `prefix`, sorted.
+    pub fn list(&self, prefix: &str) -> Result<Vec<KeyInfo>, KvError> {
+        let now = Utc::now();
+        let mut keys = self.keys()?;
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort();
+
+        let mut infos = vec![];
+        for key in keys {
+            match self.load_live(&key, now) {
+                Ok(Some(entry)) => infos.push(key_info(&key, &entry)),
+                Ok(None) => (),
+                Err(e) if is_undecodable(&e) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(infos)
+    }
+
+    fn keys(&self) -> Result<Vec<String>, KvError> {
+        Ok(Namespace::Kv.keys(self.persist.list()?))
+    }
+
+    /// Number of live keys and their total value size, purging expired and
+    /// undecodable entries.
+    /// Must be called with the write lock held.
+    fn usage(&self, now: DateTime<Utc>) -> Result<(usize, usize), KvError> {
+        let mut keys = 0;
+        let mut bytes = 0;
+        for key in self.keys()? {
+            match self.load(&key) {
+                Ok(Some(entry)) if entry.is_expired(now) => self.persist.remove(&storage_key(&key))?,
+                Ok(Some(entry)) => {
+                    keys += 1;
+                    bytes += entry.value.len();
+                }
+                Ok(None) => (),
+                Err(e) if is_undecodable(&e) => self.persist.remove(&storage_key(&key))?,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((keys, bytes))
+    }
+
+    fn load(&self, key: &str) -> Result<Option<Entry>, KvError> {
+        match self.persist.load::<Entry>(&storage_key(key)) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(e) => match KvError::from(e) {
+                KvError::NotFound => Ok(None),
+                e => Err(e),
+            },
+        }
+    }
+
+    /// Load an entry, treating it as missing once its TTL has passed.
+    fn load_live(&self, key: &str, now: DateTime<Utc>) -> Result<Option<Entry>, KvError> {
+        Ok(self.load(key)?.filter(|entry| !entry.is_expired(now)))
+    }
+}
+
+fn storage_key(key: &str) -> String {
+    Namespace::Kv.key(key)
+}
+
+fn is_undecodable(e: &KvError) -> bool {
+    matches!(e, KvError::Persist(PersistError::Deserialize(_)))
+}
+
+fn check_version(expected: Option<u64>, actual: u64) -> Result<(), KvError> {
+    match expected {
+        Some(expected) if expected != actual => Err(KvError::VersionMismatch { expected, actual }),
+        _ => Ok(()),
+    }
+}
+
+fn key_info(key: &str, entry: &Entry) -> KeyInfo {
+    KeyInfo {
+        key: key.to_string(),
+        version: entry.version,
+        size: entry.value.len(),
+        expires_at: entry.expires_at,
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::{HashMap, HashSet};
+use pulldown_cmark::{html, Options, Parser};
+use serde::Deserialize;
+
+/// Tags whose content is dropped along with them, whatever the policy says.
+const CLEAN_CONTENT_TAGS: [&str; 2] = ["script", "style"];
+/// Tags that run script, load other documents or change how the page
+/// resolves and submits URLs, even without any attribute allowed.
+const FORBIDDEN_TAGS: [&str; 15] = [
+    "applet", "base", "embed", "form", "frame", "frameset", "iframe", "link", "math", "meta", "noscript", "object",
+    "portal", "svg", "template",
+];
+/// Attributes that carry a document, a submit target or a refresh, besides
+/// the `on*` handlers and `style`.
+const FORBIDDEN_ATTRIBUTES: [&str; 6] = ["action", "formaction", "http-equiv", "srcdoc", "xlink:href", "xmlns"];
+/// Schemes that run code or embed content when followed.
+const FORBIDDEN_SCHEMES: [&str; 3] = ["javascript", "vbscript", "data"];
+
+/// Allowlist applied to the HTML rendered from Markdown. Anything not listed
+/// is removed: unknown tags are unwrapped, keeping their text, while
+/// `<script>` and `<style>` go with their content.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SanitizePolicy {
+    pub tags: Vec<String>,
+    /// Allowed attributes per tag.
+    pub attributes: HashMap<String, Vec<String>>,
+    /// Attributes allowed on every tag.
+    pub generic_attributes: Vec<String>,
+    /// Allowed schemes for absolute URLs in `href` and `src`.
+    pub url_schemes: Vec<String>,
+    /// Forced `rel` of every link; the `rel` attribute itself cannot be allowed then.
+    pub link_rel: Option<String>,
+    /// Forced `target` of every link, e.g. `_blank`.
+    pub link_target: Option<String>,
+}
+
+impl Default for SanitizePolicy {
+    fn default() -> Self {
+        let strings = |items: &[&str]| items.iter().map(|item| item.to_string()).collect::<Vec<_>>();
+        Self {
+            tags: strings(&[
+                "a", "blockquote", "br", "code", "del", "em", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "img",
+                "li", "ol", "p", "pre", "strong", "table", "tbody", "td", "th", "thead", "tr", "ul",
+            ]),
+            attributes: HashMap::from([
+                ("a".to_string(), strings(&["href", "title"])),
+                ("img".to_string(), strings(&["src", "alt", "title"])),
+                ("ol".to_string(), strings(&["start"])),
+            ]),
+            generic_attributes: vec![],
+            url_schemes: strings(&["http", "https", "mailto"]),
+            link_rel: Some("noopener noreferrer nofollow".to_string()),
+            link_target: None,
+        }
+    }
+}
+
+impl SanitizePolicy {
+    /// Reject policies that the sanitizer cannot apply, or that allow a tag,
+    /// attribute or scheme known to run script. The policy comes from the
+    /// client, so this is a denylist on top of its allowlist.
+    pub fn validate(&self) -> Result<(), String> {
+        let forbidden = self.tags.iter().find(|tag| {
+            let tag = tag.to_ascii_lowercase();
+            CLEAN_CONTENT_TAGS.contains(&tag.as_str()) || FORBIDDEN_TAGS.contains(&tag.as_str())
+        });
+        if let Some(tag) = forbidden {
+            return Err(format!("<{tag}> cannot be allowed"));
+        }
+        let attributes = self.attributes.iter()
+            .flat_map(|(tag, attributes)| attributes.iter().map(move |attribute| (tag.as_str(), attribute.as_str())))
+            .chain(self.generic_attributes.iter().map(|attribute| ("*", attribute.as_str())));
+        for (tag, attribute) in attributes {
+            let attribute = attribute.to_ascii_lowercase();
+            let forbidden = FORBIDDEN_ATTRIBUTES.contains(&attribute.as_str());
+            if forbidden || attribute.starts_with("on") || attribute == "style" {
+                return Err(format!("Attribute {attribute} cannot be allowed"));
+            }
+            let is_link = tag == "a" || tag == "*";
+            if is_link && attribute == "rel" && self.link_rel.is_some() {
+                return Err("Attribute rel cannot be allowed together with link_rel".to_string());
+            }
+            if is_link && attribute == "target" && self.link_target.is_some() {
+                return Err("Attribute target cannot be allowed together with link_target".to_string());
+            }
+        }
+        let forbidden = self.url_schemes.iter()
+            .find(|scheme| FORBIDDEN_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()));
+        if let Some(scheme) = forbidden {
+            return Err(format!("URL scheme {scheme} cannot be allowed"));
+        }
+        Ok(())
+    }
+
+    pub fn clean(&self, html: &str) -> String {
+        let mut builder = ammonia::Builder::empty();
+        builder
+            .tags(self.tags.iter().map(String::as_str).collect())
+            .clean_content_tags(HashSet::from(CLEAN_CONTENT_TAGS))
+            .tag_attributes(self.attributes.iter()
+                .map(|(tag, attributes)| (tag.as_str(), attributes.iter().map(String::as_str).collect()))
+                .collect())
+            .generic_attributes(self.generic_attributes.iter().map(String::as_str).collect())
+            .url_schemes(self.url_schemes.iter().map(String::as_str).collect())
+            .link_rel(self.link_rel.as_deref())
+            .strip_comments(true);
+        if let Some(target) = &self.link_target {
+            builder.set_tag_attribute_value("a", "target", target.as_str());
+        }
+        builder.clean(html).to_string()
+    }
+}
+
+/// Render CommonMark with tables and strikethrough to HTML, then sanitize it.
+/// Raw HTML in the Markdown goes through the same policy.
+pub fn render(markdown: &str, policy: &SanitizePolicy) -> Result<String, String> {
+    policy.validate()?;
+
+    let parser = Parser::new_ext(markdown, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH);
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, parser);
+
+    Ok(policy.clean(&unsafe_html))
+}
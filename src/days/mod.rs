@@ -1,3 +1,9 @@
+pub mod assets;
+pub mod breach;
+pub mod chat;
+#[cfg(not(feature = "sqlite"))]
+pub mod chat_pg;
+pub mod csv_io;
 pub mod d01;
 pub mod d04;
 pub mod d05;
@@ -14,3 +20,17 @@ pub mod d19;
 pub mod d20;
 pub mod d21;
 pub mod d22;
+pub mod imaging;
+pub mod kv;
+pub mod markdown;
+pub mod namespace;
+pub mod orders;
+#[cfg(not(feature = "sqlite"))]
+pub mod orders_pg;
+#[cfg(feature = "sqlite")]
+pub mod orders_sqlite;
+pub mod password;
+pub mod security;
+pub mod sequence;
+pub mod timers;
+pub mod ulids;
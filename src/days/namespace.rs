@@ -0,0 +1,32 @@
+/// Day 12 keeps plain keys, timers and KV entries in one `PersistInstance`.
+/// Each lives under its own prefix, so a key written through one endpoint can
+/// neither overwrite nor show up as an entry of another.
+#[derive(Clone, Copy, Debug)]
+pub enum Namespace {
+    /// `/12/save/:key` and `/12/load/:key`.
+    Plain,
+    Timer,
+    Kv,
+}
+
+impl Namespace {
+    fn prefix(self) -> &'static str {
+        match self {
+            Namespace::Plain => "key.",
+            Namespace::Timer => "timer.",
+            Namespace::Kv => "kv.",
+        }
+    }
+
+    /// Persist key under which `key` is stored.
+    pub fn key(self, key: &str) -> String {
+        format!("{}{key}", self.prefix())
+    }
+
+    /// Keys of this namespace among the persist keys `stored`, without the prefix.
+    pub fn keys(self, stored: Vec<String>) -> Vec<String> {
+        stored.into_iter()
+            .filter_map(|key| key.strip_prefix(self.prefix()).map(str::to_string))
+            .collect()
+    }
+}
@@ -0,0 +1,453 @@
+use std::collections::BTreeMap;
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tokio::sync::{mpsc, oneshot};
+
+/// Directory holding named JSON fixtures for `reset`.
+const FIXTURES_DIR: &str = "fixtures";
+
+#[derive(Deserialize, Serialize, FromRow, Clone, Debug)]
+pub struct Order {
+    pub id: i32,
+    pub region_id: i32,
+    pub gift_name: String,
+    pub quantity: i32,
+    /// Defaults to the insert time; kept as is when an upsert updates the row.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, FromRow, Clone, Debug)]
+pub struct Region {
+    pub id: i32,
+    pub name: String,
+    /// Enclosing region, e.g. the country of a city.
+    #[serde(default)]
+    pub parent_id: Option<i32>,
+}
+
+/// Replacement values for an existing region.
+#[derive(Deserialize, Debug)]
+pub struct RegionUpdate {
+    pub name: String,
+    #[serde(default)]
+    pub parent_id: Option<i32>,
+}
+
+/// What to do with rows whose `id` already exists.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Fail the whole batch; nothing is written.
+    #[default]
+    Reject,
+    /// Keep the existing row and ignore the new one.
+    Skip,
+    /// Overwrite the existing row.
+    Upsert,
+}
+
+/// What to do with orders whose region does not exist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissingRegions {
+    /// Fail on the foreign key, like any other constraint.
+    #[default]
+    Reject,
+    /// Create a placeholder region named after the id, e.g. `Region 7`, in the
+    /// same transaction. Day 13 loads orders without ever loading regions.
+    /// Placeholders go away on the next Day 13 reset, unless they have been
+    /// written through Day 18 since.
+    Create,
+}
+
+/// Outcome of `OrderStore::delete_region`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionDeletion {
+    Deleted,
+    NotFound,
+    /// Orders still refer to the region, so it was kept.
+    HasOrders,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct InsertParams {
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct InsertSummary {
+    pub received: usize,
+    pub inserted: usize,
+    pub updated: usize,
+    pub skipped: usize,
+}
+
+/// Map database errors to responses; duplicate ids are a conflict, not a server error.
+pub fn db_error(e: sqlx::Error) -> (StatusCode, String) {
+    match e {
+        sqlx::Error::Database(ref db) if db.is_unique_violation() =>
+            (StatusCode::CONFLICT, db.message().to_string()),
+        sqlx::Error::Database(ref db) if db.is_foreign_key_violation() || db.is_check_violation() =>
+            (StatusCode::UNPROCESSABLE_ENTITY, db.message().to_string()),
+        e => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// Within one upsert statement a row can only be touched once, so the last
+/// occurrence of each id wins.
+pub(crate) fn dedup_by_id<T: Clone>(rows: &[T], id: impl Fn(&T) -> i32) -> Vec<T> {
+    rows.iter()
+        .map(|row| (id(row), row.clone()))
+        .collect::<BTreeMap<_, _>>()
+        .into_values()
+        .collect()
+}
+
+/// Initial data for `reset`, read from `fixtures/<name>.json`.
+#[derive(Deserialize, Debug, Default)]
+pub struct Fixture {
+    #[serde(default)]
+    pub regions: Vec<Region>,
+    #[serde(default)]
+    pub orders: Vec<Order>,
+}
+
+pub async fn load_fixture(name: &str) -> Result<Fixture, (StatusCode, String)> {
+    let valid = !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid fixture name {name}")));
+    }
+
+    let path = std::path::Path::new(FIXTURES_DIR).join(format!("{name}.json"));
+    let json = tokio::fs::read(&path)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, format!("Fixture {name} not found")))?;
+
+    serde_json::from_slice(&json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Invalid fixture {name}: {e}")))
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ResetParams {
+    /// Fixture to load after clearing the tables.
+    pub seed: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ResetSummary {
+    pub regions: InsertSummary,
+    pub orders: InsertSummary,
+}
+
+/// Which orders to include. Every filter is optional and they combine with AND;
+/// the time range is half-open, `from <= created_at < to`.
+#[derive(Deserialize, Debug, Default)]
+pub struct OrderFilter {
+    pub gift: Option<String>,
+    pub region_id: Option<i32>,
+    pub min_quantity: Option<i32>,
+    pub max_quantity: Option<i32>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Gift,
+    Region,
+}
+
+/// Aggregates over the filtered orders. With no matching orders `count` and
+/// `total` are 0 while the statistics that need at least one row are null.
+#[derive(Serialize, Debug)]
+pub struct OrderStats {
+    pub count: i64,
+    pub total: i64,
+    pub average: Option<f64>,
+    pub min: Option<i32>,
+    pub max: Option<i32>,
+    /// Quantity percentiles keyed as `p50`, `p90`, ...
+    pub percentiles: BTreeMap<String, Option<f64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<OrderGroup>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OrderGroup {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gift: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region_id: Option<i32>,
+    /// Region name, null for orders whose region is unknown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<Option<String>>,
+    pub count: i64,
+    pub total: i64,
+    pub average: f64,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    Hour,
+    Day,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SeriesPoint {
+    /// Start of the bucket.
+    pub bucket: DateTime<Utc>,
+    pub count: i64,
+    pub total: i64,
+}
+
+#[derive(Serialize, FromRow, Debug, Default)]
+pub struct RegionTotal {
+    pub region: String,
+    pub total: i64,
+}
+
+/// How gifts are ranked within a region.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Ranking {
+    /// Consecutive ranks, ties broken alphabetically; exactly `limit` gifts.
+    #[default]
+    Row,
+    /// Equal values share a rank and the next value gets the next rank, so
+    /// `limit` counts distinct values.
+    Dense,
+    /// Equal values share a rank and ranks are skipped after them; every gift
+    /// tied at the cutoff is kept.
+    Ties,
+}
+
+impl Ranking {
+    /// Window expression ranking the rows of `gift_totals` by `total`. Only
+    /// `row_number` breaks ties by name; the others must see equal totals as peers.
+    pub(crate) fn window(self) -> &'static str {
+        match self {
+            Ranking::Row => "row_number() OVER (PARTITION BY region_id ORDER BY total DESC, gift_name ASC)",
+            Ranking::Dense => "dense_rank() OVER (PARTITION BY region_id ORDER BY total DESC)",
+            Ranking::Ties => "rank() OVER (PARTITION BY region_id ORDER BY total DESC)",
+        }
+    }
+}
+
+/// What gifts are ranked by.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    /// Total quantity ordered.
+    #[default]
+    Quantity,
+    /// Number of orders.
+    Count,
+}
+
+impl Metric {
+    /// Aggregate over `orders o` computing the metric.
+    pub(crate) fn aggregate(self) -> &'static str {
+        match self {
+            Metric::Quantity => "sum(o.quantity)",
+            Metric::Count => "count(*)",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TopGiftsQuery {
+    /// Largest rank to include.
+    pub limit: i32,
+    /// Sub-region levels rolled up into each region, see `region_totals`.
+    pub depth: Option<i32>,
+    pub ranking: Ranking,
+    pub metric: Metric,
+    /// Only report these regions; all of them when `None`.
+    pub regions: Option<Vec<i32>>,
+}
+
+/// One row of the ranking query: a ranked gift, or a region without gifts
+/// with `gift_name`, `rank` and `total` null.
+#[derive(FromRow, Debug)]
+pub(crate) struct RankedGift {
+    pub region_id: i32,
+    pub region: String,
+    pub region_total: i64,
+    pub gift_name: Option<String>,
+    pub rank: Option<i64>,
+    pub total: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct GiftRank {
+    pub gift: String,
+    pub rank: i64,
+    /// Value of the ranking metric.
+    pub total: i64,
+    /// Fraction of the region's total, 0 to 1.
+    pub share: f64,
+}
+
+/// Top gifts of one region with their totals.
+#[derive(Serialize, Debug)]
+pub struct RegionTopGifts {
+    pub region: String,
+    /// Value of the ranking metric over all gifts of the region.
+    pub total: i64,
+    pub top_gifts: Vec<GiftRank>,
+}
+
+/// Collect ranked rows, sorted by region, into one entry per region.
+pub(crate) fn group_top_gifts(rows: Vec<RankedGift>) -> Vec<RegionTopGifts> {
+    let mut regions = Vec::<RegionTopGifts>::new();
+    let mut current = None;
+    for row in rows {
+        if current != Some(row.region_id) {
+            current = Some(row.region_id);
+            regions.push(RegionTopGifts { region: row.region, total: row.region_total, top_gifts: vec![] });
+        }
+        if let (Some(gift), Some(rank), Some(total), Some(last)) = (row.gift_name, row.rank, row.total, regions.last_mut()) {
+            let share = if last.total == 0 { 0.0 } else { total as f64 / last.total as f64 };
+            last.top_gifts.push(GiftRank { gift, rank, total, share });
+        }
+    }
+    regions
+}
+
+/// Gift names only, the original shape of the top list.
+#[derive(Serialize, Debug, Default)]
+pub struct TopGifts {
+    pub region: String,
+    pub top_gifts: Vec<String>,
+}
+
+impl From<RegionTopGifts> for TopGifts {
+    fn from(top: RegionTopGifts) -> Self {
+        Self {
+            region: top.region,
+            top_gifts: top.top_gifts.into_iter().map(|gift| gift.gift).collect(),
+        }
+    }
+}
+
+/// Chunks of a CSV document, header line first.
+pub type CsvChunks = BoxStream<'static, Result<Vec<u8>, sqlx::Error>>;
+
+/// Serialize `rows` as one CSV chunk with a header line.
+pub fn csv_chunks<T: Serialize>(rows: &[T]) -> CsvChunks {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    let csv = rows.iter()
+        .try_for_each(|row| writer.serialize(row))
+        .map_err(std::io::Error::from)
+        .and_then(|_| writer.into_inner().map_err(|e| e.into_error()))
+        .map_err(sqlx::Error::Io);
+    Box::pin(futures::stream::once(async move { csv }))
+}
+
+/// Storage behind the Day 13 and Day 18 endpoints.
+///
+/// `PgStore` is the default; `SqliteStore` (feature `sqlite`) keeps everything
+/// in an embedded database file. Both report constraint failures as database
+/// errors of the matching kind, so `db_error` maps them the same way.
+#[async_trait]
+pub trait OrderStore: Send + Sync {
+    /// Round trip `number` through the database.
+    async fn select(&self, number: i32) -> Result<i32, sqlx::Error>;
+
+    /// Empty the tables (regions too if `include_regions`, otherwise only the placeholder
+    /// regions) and load `fixture`, all in one transaction. Fixture regions that were kept
+    /// are skipped. Only data is touched; the schema is owned by the migrations.
+    async fn reset(&self, include_regions: bool, fixture: Option<Fixture>) -> Result<ResetSummary, sqlx::Error>;
+
+    async fn insert_orders(
+        &self,
+        orders: &[Order],
+        on_conflict: OnConflict,
+        missing: MissingRegions,
+    ) -> Result<InsertSummary, sqlx::Error>;
+
+    async fn insert_regions(&self, regions: &[Region], on_conflict: OnConflict) -> Result<InsertSummary, sqlx::Error>;
+
+    /// Load batches of orders in one transaction, then commit if `proceed`
+    /// says so. Returns the inserted and updated counts, or `None` when the
+    /// import was abandoned.
+    async fn import_orders(
+        &self,
+        batches: mpsc::Receiver<Vec<Order>>,
+        on_conflict: OnConflict,
+        missing: MissingRegions,
+        proceed: oneshot::Receiver<bool>,
+    ) -> Result<Option<(usize, usize)>, sqlx::Error>;
+
+    /// Like `import_orders`, for regions.
+    async fn import_regions(
+        &self,
+        batches: mpsc::Receiver<Vec<Region>>,
+        on_conflict: OnConflict,
+        proceed: oneshot::Receiver<bool>,
+    ) -> Result<Option<(usize, usize)>, sqlx::Error>;
+
+    /// Sum of all quantities, 0 without orders.
+    async fn total_quantity(&self) -> Result<i64, sqlx::Error>;
+
+    /// Gift with the largest total quantity.
+    async fn popular_gift(&self) -> Result<Option<String>, sqlx::Error>;
+
+    /// Summary statistics for the filtered orders and, with `group_by`, per gift or
+    /// region sorted by total quantity, limited to the `top` largest groups.
+    async fn order_stats(
+        &self,
+        filter: &OrderFilter,
+        percentiles: &[f64],
+        group_by: Option<GroupBy>,
+        top: Option<i64>,
+    ) -> Result<OrderStats, sqlx::Error>;
+
+    /// Order count and quantity per hour or day, with buckets aligned to midnight in
+    /// `tz`. Buckets without orders are left out.
+    async fn order_series(
+        &self,
+        filter: &OrderFilter,
+        interval: Interval,
+        tz: Tz,
+    ) -> Result<Vec<SeriesPoint>, sqlx::Error>;
+
+    async fn list_orders(&self) -> Result<Vec<Order>, sqlx::Error>;
+
+    async fn list_regions(&self) -> Result<Vec<Region>, sqlx::Error>;
+
+    async fn get_region(&self, id: i32) -> Result<Option<Region>, sqlx::Error>;
+
+    /// Rename or move a region. Moving it below one of its own descendants is a
+    /// check violation.
+    async fn update_region(&self, id: i32, update: &RegionUpdate) -> Result<Option<Region>, sqlx::Error>;
+
+    /// Delete a region without children. A region that orders refer to is kept.
+    async fn delete_region(&self, id: i32) -> Result<RegionDeletion, sqlx::Error>;
+
+    /// Total quantity per region including sub-regions down to `depth` levels,
+    /// all of them when `None`. Regions without orders are left out.
+    async fn region_totals(&self, depth: Option<i32>) -> Result<Vec<RegionTotal>, sqlx::Error>;
+
+    /// Gifts ranked per region by `query.metric`, largest first and ties in
+    /// alphabetical order, counting sub-regions like `region_totals`. Every
+    /// region is listed, sorted by name, even without gifts.
+    async fn top_gifts(&self, query: &TopGiftsQuery) -> Result<Vec<RegionTopGifts>, sqlx::Error>;
+
+    async fn orders_csv(&self) -> Result<CsvChunks, sqlx::Error> {
+        Ok(csv_chunks(&self.list_orders().await?))
+    }
+
+    async fn regions_csv(&self) -> Result<CsvChunks, sqlx::Error> {
+        Ok(csv_chunks(&self.list_regions().await?))
+    }
+}
@@ -0,0 +1,835 @@
+use async_trait::async_trait;
+use chrono_tz::Tz;
+use futures::StreamExt;
+use sqlx::{PgConnection, PgPool, Row};
+use tokio::sync::{mpsc, oneshot};
+use crate::days::orders::{
+    dedup_by_id, group_top_gifts, CsvChunks, Fixture, GroupBy, InsertSummary, Interval, MissingRegions, OnConflict,
+    Order, OrderFilter, OrderGroup, OrderStats, OrderStore, RankedGift, Region, RegionDeletion, RegionTopGifts,
+    RegionTotal, RegionUpdate, ResetSummary, SeriesPoint, TopGiftsQuery,
+};
+
+/// Postgres storage. Queries are checked against the schema at compile time
+/// wherever they are static.
+#[derive(Clone)]
+pub struct PgStore {
+    pool: PgPool,
+}
+
+impl PgStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+/// Insert all orders with a single multi-row statement inside a transaction.
+async fn insert_orders(
+    pool: &PgPool,
+    orders: &[Order],
+    on_conflict: OnConflict,
+    missing: MissingRegions,
+) -> Result<InsertSummary, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    if missing == MissingRegions::Create {
+        let region_ids = orders.iter().map(|o| o.region_id).collect::<Vec<_>>();
+        sqlx::query!(
+            r"
+            INSERT INTO regions (id, name, placeholder)
+            SELECT DISTINCT id, 'Region ' || id, true FROM UNNEST($1::INT[]) AS t (id)
+            ON CONFLICT (id) DO NOTHING
+            ",
+            &region_ids
+        )
+            .execute(&mut *tx)
+            .await?;
+    }
+    let summary = insert_orders_in(&mut tx, orders, on_conflict).await?;
+    tx.commit().await?;
+    Ok(summary)
+}
+
+/// Insert all orders with a single multi-row statement on an open connection.
+async fn insert_orders_in(
+    conn: &mut PgConnection,
+    orders: &[Order],
+    on_conflict: OnConflict,
+) -> Result<InsertSummary, sqlx::Error> {
+    let rows = match on_conflict {
+        OnConflict::Upsert => dedup_by_id(orders, |o| o.id),
+        _ => orders.to_vec(),
+    };
+    let ids = rows.iter().map(|o| o.id).collect::<Vec<_>>();
+    let region_ids = rows.iter().map(|o| o.region_id).collect::<Vec<_>>();
+    let gift_names = rows.iter().map(|o| o.gift_name.clone()).collect::<Vec<_>>();
+    let quantities = rows.iter().map(|o| o.quantity).collect::<Vec<_>>();
+    let created_ats = rows.iter().map(|o| o.created_at).collect::<Vec<_>>();
+
+    let (inserted, updated) = match on_conflict {
+        OnConflict::Reject => {
+            let result = sqlx::query!(
+                r"
+                INSERT INTO orders (id, region_id, gift_name, quantity, created_at)
+                SELECT id, region_id, gift_name, quantity, COALESCE(created_at, now())
+                FROM UNNEST($1::INT[], $2::INT[], $3::VARCHAR[], $4::INT[], $5::TIMESTAMPTZ[])
+                    AS t (id, region_id, gift_name, quantity, created_at)
+                ",
+                &ids, &region_ids, &gift_names, &quantities, &created_ats as _
+            )
+                .execute(&mut *conn)
+                .await?;
+            (result.rows_affected() as usize, 0)
+        }
+        OnConflict::Skip => {
+            let result = sqlx::query!(
+                r"
+                INSERT INTO orders (id, region_id, gift_name, quantity, created_at)
+                SELECT id, region_id, gift_name, quantity, COALESCE(created_at, now())
+                FROM UNNEST($1::INT[], $2::INT[], $3::VARCHAR[], $4::INT[], $5::TIMESTAMPTZ[])
+                    AS t (id, region_id, gift_name, quantity, created_at)
+                ON CONFLICT (id) DO NOTHING
+                ",
+                &ids, &region_ids, &gift_names, &quantities, &created_ats as _
+            )
+                .execute(&mut *conn)
+                .await?;
+            (result.rows_affected() as usize, 0)
+        }
+        OnConflict::Upsert => {
+            // `xmax = 0` only holds for freshly inserted rows.
+            let rows = sqlx::query!(
+                r#"
+                INSERT INTO orders (id, region_id, gift_name, quantity, created_at)
+                SELECT id, region_id, gift_name, quantity, COALESCE(created_at, now())
+                FROM UNNEST($1::INT[], $2::INT[], $3::VARCHAR[], $4::INT[], $5::TIMESTAMPTZ[])
+                    AS t (id, region_id, gift_name, quantity, created_at)
+                ON CONFLICT (id) DO UPDATE
+                    SET region_id = EXCLUDED.region_id,
+                        gift_name = EXCLUDED.gift_name,
+                        quantity = EXCLUDED.quantity
+                RETURNING (xmax = 0) AS "inserted!"
+                "#,
+                &ids, &region_ids, &gift_names, &quantities, &created_ats as _
+            )
+                .fetch_all(&mut *conn)
+                .await?;
+            let inserted = rows.iter().filter(|row| row.inserted).count();
+            (inserted, rows.len() - inserted)
+        }
+    };
+
+    Ok(InsertSummary {
+        received: orders.len(),
+        inserted,
+        updated,
+        skipped: orders.len() - inserted - updated,
+    })
+}
+
+/// Insert all regions with a single multi-row statement inside a transaction.
+async fn insert_regions(
+    pool: &PgPool,
+    regions: &[Region],
+    on_conflict: OnConflict,
+) -> Result<InsertSummary, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let summary = insert_regions_in(&mut tx, regions, on_conflict).await?;
+    tx.commit().await?;
+    Ok(summary)
+}
+
+/// Insert all regions with a single multi-row statement on an open connection.
+async fn insert_regions_in(
+    conn: &mut PgConnection,
+    regions: &[Region],
+    on_conflict: OnConflict,
+) -> Result<InsertSummary, sqlx::Error> {
+    let rows = match on_conflict {
+        OnConflict::Upsert => dedup_by_id(regions, |r| r.id),
+        _ => regions.to_vec(),
+    };
+    let ids = rows.iter().map(|r| r.id).collect::<Vec<_>>();
+    let names = rows.iter().map(|r| r.name.clone()).collect::<Vec<_>>();
+    let parent_ids = rows.iter().map(|r| r.parent_id).collect::<Vec<_>>();
+
+    let (inserted, updated) = match on_conflict {
+        OnConflict::Reject => {
+            let result = sqlx::query!(
+                r"
+                INSERT INTO regions (id, name, parent_id)
+                SELECT * FROM UNNEST($1::INT[], $2::VARCHAR[], $3::INT[])
+                ",
+                &ids, &names, &parent_ids as _
+            )
+                .execute(&mut *conn)
+                .await?;
+            (result.rows_affected() as usize, 0)
+        }
+        OnConflict::Skip => {
+            let result = sqlx::query!(
+                r"
+                INSERT INTO regions (id, name, parent_id)
+                SELECT * FROM UNNEST($1::INT[], $2::VARCHAR[], $3::INT[])
+                ON CONFLICT (id) DO NOTHING
+                ",
+                &ids, &names, &parent_ids as _
+            )
+                .execute(&mut *conn)
+                .await?;
+            (result.rows_affected() as usize, 0)
+        }
+        OnConflict::Upsert => {
+            let rows = sqlx::query!(
+                r#"
+                INSERT INTO regions (id, name, parent_id)
+                SELECT * FROM UNNEST($1::INT[], $2::VARCHAR[], $3::INT[])
+                ON CONFLICT (id) DO UPDATE
+                    SET name = EXCLUDED.name,
+                        parent_id = EXCLUDED.parent_id,
+                        placeholder = false
+                RETURNING (xmax = 0) AS "inserted!"
+                "#,
+                &ids, &names, &parent_ids as _
+            )
+                .fetch_all(&mut *conn)
+                .await?;
+            let inserted = rows.iter().filter(|row| row.inserted).count();
+            (inserted, rows.len() - inserted)
+        }
+    };
+
+    Ok(InsertSummary {
+        received: regions.len(),
+        inserted,
+        updated,
+        skipped: regions.len() - inserted - updated,
+    })
+}
+
+async fn list_orders(pool: &PgPool) -> Result<Vec<Order>, sqlx::Error> {
+    sqlx::query_as!(
+        Order,
+        r#"SELECT id, region_id, gift_name, quantity, created_at AS "created_at?" FROM orders ORDER BY id"#
+    )
+        .fetch_all(pool)
+        .await
+}
+
+async fn list_regions(pool: &PgPool) -> Result<Vec<Region>, sqlx::Error> {
+    sqlx::query_as!(Region, "SELECT id, name, parent_id FROM regions ORDER BY id")
+        .fetch_all(pool)
+        .await
+}
+
+async fn get_region(pool: &PgPool, id: i32) -> Result<Option<Region>, sqlx::Error> {
+    sqlx::query_as!(Region, "SELECT id, name, parent_id FROM regions WHERE id = $1", id)
+        .fetch_optional(pool)
+        .await
+}
+
+async fn update_region(
+    pool: &PgPool,
+    id: i32,
+    update: &RegionUpdate,
+) -> Result<Option<Region>, sqlx::Error> {
+    sqlx::query_as!(
+        Region,
+        "UPDATE regions SET name = $2, parent_id = $3, placeholder = false WHERE id = $1 RETURNING id, name, parent_id",
+        id, update.name, update.parent_id
+    )
+        .fetch_optional(pool)
+        .await
+}
+
+async fn delete_region(pool: &PgPool, id: i32) -> Result<RegionDeletion, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let has_orders = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM orders WHERE region_id = $1) AS "has_orders!""#,
+        id
+    )
+        .fetch_one(&mut *tx)
+        .await?;
+    if has_orders {
+        return Ok(RegionDeletion::HasOrders);
+    }
+
+    let result = sqlx::query!("DELETE FROM regions WHERE id = $1", id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(if result.rows_affected() > 0 { RegionDeletion::Deleted } else { RegionDeletion::NotFound })
+}
+
+async fn reset(
+    pool: &PgPool,
+    include_regions: bool,
+    fixture: Option<Fixture>,
+) -> Result<ResetSummary, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    if include_regions {
+        sqlx::query!("TRUNCATE orders, regions")
+            .execute(&mut *tx)
+            .await?;
+    } else {
+        sqlx::query!("TRUNCATE orders")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!(
+            r"
+            DELETE FROM regions
+            WHERE placeholder
+                AND id NOT IN (SELECT parent_id FROM regions WHERE parent_id IS NOT NULL)
+            "
+        )
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let mut summary = ResetSummary::default();
+    if let Some(fixture) = fixture {
+        // Kept regions may already hold the fixture's, e.g. on a second Day 13 reset.
+        let regions = if include_regions { OnConflict::Reject } else { OnConflict::Skip };
+        summary.regions = insert_regions_in(&mut tx, &fixture.regions, regions).await?;
+        summary.orders = insert_orders_in(&mut tx, &fixture.orders, OnConflict::Reject).await?;
+    }
+
+    tx.commit().await?;
+    Ok(summary)
+}
+
+async fn order_stats(
+    pool: &PgPool,
+    filter: &OrderFilter,
+    percentiles: &[f64],
+    group_by: Option<GroupBy>,
+    top: Option<i64>,
+) -> Result<OrderStats, sqlx::Error> {
+    let fractions = percentiles.iter().map(|p| p / 100.0).collect::<Vec<_>>();
+
+    let summary = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!",
+               COALESCE(SUM(quantity), 0)::BIGINT AS "total!",
+               AVG(quantity)::FLOAT8 AS average,
+               MIN(quantity) AS min,
+               MAX(quantity) AS max,
+               percentile_cont($7::FLOAT8[]) WITHIN GROUP (ORDER BY quantity) AS percentiles
+        FROM orders
+        WHERE ($1::VARCHAR IS NULL OR gift_name = $1)
+          AND ($2::INT IS NULL OR region_id = $2)
+          AND ($3::INT IS NULL OR quantity >= $3)
+          AND ($4::INT IS NULL OR quantity <= $4)
+          AND ($5::TIMESTAMPTZ IS NULL OR created_at >= $5)
+          AND ($6::TIMESTAMPTZ IS NULL OR created_at < $6)
+        "#,
+        filter.gift, filter.region_id, filter.min_quantity, filter.max_quantity,
+        filter.from, filter.to, &fractions
+    )
+        .fetch_one(pool)
+        .await?;
+
+    let values = summary.percentiles.unwrap_or_default();
+    let percentiles = percentiles.iter()
+        .enumerate()
+        .map(|(i, p)| (format!("p{p}"), values.get(i).copied()))
+        .collect();
+
+    let groups = match group_by {
+        None => None,
+        Some(GroupBy::Gift) => Some(sqlx::query!(
+            r#"
+            SELECT gift_name,
+                   COUNT(*) AS "count!",
+                   SUM(quantity)::BIGINT AS "total!",
+                   AVG(quantity)::FLOAT8 AS "average!"
+            FROM orders
+            WHERE ($1::VARCHAR IS NULL OR gift_name = $1)
+              AND ($2::INT IS NULL OR region_id = $2)
+              AND ($3::INT IS NULL OR quantity >= $3)
+              AND ($4::INT IS NULL OR quantity <= $4)
+              AND ($5::TIMESTAMPTZ IS NULL OR created_at >= $5)
+              AND ($6::TIMESTAMPTZ IS NULL OR created_at < $6)
+            GROUP BY gift_name
+            ORDER BY 3 DESC, gift_name
+            LIMIT $7
+            "#,
+            filter.gift, filter.region_id, filter.min_quantity, filter.max_quantity,
+            filter.from, filter.to, top
+        )
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|row| OrderGroup {
+                gift: Some(row.gift_name),
+                region_id: None,
+                region: None,
+                count: row.count,
+                total: row.total,
+                average: row.average,
+            })
+            .collect()),
+        Some(GroupBy::Region) => Some(sqlx::query!(
+            r#"
+            SELECT orders.region_id,
+                   regions.name AS "name?",
+                   COUNT(*) AS "count!",
+                   SUM(quantity)::BIGINT AS "total!",
+                   AVG(quantity)::FLOAT8 AS "average!"
+            FROM orders
+            LEFT JOIN regions ON regions.id = orders.region_id
+            WHERE ($1::VARCHAR IS NULL OR gift_name = $1)
+              AND ($2::INT IS NULL OR region_id = $2)
+              AND ($3::INT IS NULL OR quantity >= $3)
+              AND ($4::INT IS NULL OR quantity <= $4)
+              AND ($5::TIMESTAMPTZ IS NULL OR created_at >= $5)
+              AND ($6::TIMESTAMPTZ IS NULL OR created_at < $6)
+            GROUP BY orders.region_id, regions.name
+            ORDER BY 4 DESC, orders.region_id
+            LIMIT $7
+            "#,
+            filter.gift, filter.region_id, filter.min_quantity, filter.max_quantity,
+            filter.from, filter.to, top
+        )
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|row| OrderGroup {
+                gift: None,
+                region_id: Some(row.region_id),
+                region: Some(row.name),
+                count: row.count,
+                total: row.total,
+                average: row.average,
+            })
+            .collect()),
+    };
+
+    Ok(OrderStats {
+        count: summary.count,
+        total: summary.total,
+        average: summary.average,
+        min: summary.min,
+        max: summary.max,
+        percentiles,
+        groups,
+    })
+}
+
+/// `date_trunc` field name of `interval`.
+fn interval_unit(interval: Interval) -> &'static str {
+    match interval {
+        Interval::Hour => "hour",
+        Interval::Day => "day",
+    }
+}
+
+async fn order_series(
+    pool: &PgPool,
+    filter: &OrderFilter,
+    interval: Interval,
+    tz: &str,
+) -> Result<Vec<SeriesPoint>, sqlx::Error> {
+    sqlx::query_as!(
+        SeriesPoint,
+        r#"
+        SELECT date_trunc($7, created_at, $8) AS "bucket!",
+               COUNT(*) AS "count!",
+               SUM(quantity)::BIGINT AS "total!"
+        FROM orders
+        WHERE ($1::VARCHAR IS NULL OR gift_name = $1)
+          AND ($2::INT IS NULL OR region_id = $2)
+          AND ($3::INT IS NULL OR quantity >= $3)
+          AND ($4::INT IS NULL OR quantity <= $4)
+          AND ($5::TIMESTAMPTZ IS NULL OR created_at >= $5)
+          AND ($6::TIMESTAMPTZ IS NULL OR created_at < $6)
+        GROUP BY 1
+        ORDER BY 1
+        "#,
+        filter.gift, filter.region_id, filter.min_quantity, filter.max_quantity,
+        filter.from, filter.to, interval_unit(interval), tz
+    )
+        .fetch_all(pool)
+        .await
+}
+
+async fn region_totals(pool: &PgPool, depth: Option<i32>) -> Result<Vec<RegionTotal>, sqlx::Error> {
+    // `tree` pairs every region with itself and its descendants down to `depth`.
+    sqlx::query_as::<_, RegionTotal>(
+        r#"
+            WITH RECURSIVE tree (ancestor, id, level) AS (
+                SELECT id, id, 0 FROM regions
+                UNION ALL
+                SELECT t.ancestor, r.id, t.level + 1
+                FROM tree t
+                    JOIN regions r
+                        ON r.parent_id = t.id
+                WHERE $1::INT IS NULL OR t.level < $1
+            )
+            SELECT a.name AS region,
+                sum(o.quantity) AS total
+            FROM tree t
+                JOIN regions a
+                    ON a.id = t.ancestor
+                JOIN orders o
+                    ON o.region_id = t.id
+            GROUP BY a.id, a.name
+            ORDER BY a.name ASC
+        "#,
+    )
+        .bind(depth)
+        .fetch_all(pool)
+        .await
+}
+
+async fn top_gifts(pool: &PgPool, query: &TopGiftsQuery) -> Result<Vec<RegionTopGifts>, sqlx::Error> {
+    let sql = format!(
+        r#"
+            WITH RECURSIVE tree (ancestor, id, level) AS (
+                SELECT id, id, 0 FROM regions
+                UNION ALL
+                SELECT t.ancestor, r.id, t.level + 1
+                FROM tree t
+                    JOIN regions r
+                        ON r.parent_id = t.id
+                WHERE $2::INT IS NULL OR t.level < $2
+            ),
+            gift_totals AS (
+                SELECT t.ancestor AS region_id,
+                    o.gift_name,
+                    {aggregate}::BIGINT AS total
+                FROM tree t
+                    JOIN orders o
+                        ON o.region_id = t.id
+                GROUP BY t.ancestor, o.gift_name
+            ),
+            region_totals AS (
+                SELECT region_id,
+                    sum(total)::BIGINT AS total
+                FROM gift_totals
+                GROUP BY region_id
+            ),
+            ranked AS (
+                SELECT region_id,
+                    gift_name,
+                    total,
+                    {rank} AS rank
+                FROM gift_totals
+            )
+            SELECT r.id AS region_id,
+                r.name AS region,
+                COALESCE(rt.total, 0) AS region_total,
+                k.gift_name,
+                k.rank,
+                k.total
+            FROM regions r
+                LEFT JOIN region_totals rt
+                    ON rt.region_id = r.id
+                LEFT JOIN ranked k
+                    ON k.region_id = r.id
+                    AND k.rank <= $1
+            WHERE $3::INT[] IS NULL OR r.id = ANY($3)
+            ORDER BY r.name ASC, r.id, k.rank, k.gift_name
+        "#,
+        aggregate = query.metric.aggregate(),
+        rank = query.ranking.window(),
+    );
+    let rows = sqlx::query_as::<_, RankedGift>(&sql)
+        .bind(query.limit)
+        .bind(query.depth)
+        .bind(&query.regions)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(group_top_gifts(rows))
+}
+
+/// Rows bulk loaded with `COPY` into a staging table and merged into the real
+/// table in one statement.
+trait Staged: Send + 'static {
+    const STAGING: &'static str;
+    const COPY: &'static str;
+
+    /// Write the row in `COPY` column order.
+    fn write(&self, writer: &mut csv::Writer<Vec<u8>>) -> csv::Result<()>;
+    /// `INSERT ... SELECT` from the staging table, returning `(xmax = 0) AS inserted`.
+    fn merge(on_conflict: OnConflict) -> String;
+}
+
+/// Creates the regions staged orders refer to, see `MissingRegions::Create`.
+const STAGED_PLACEHOLDERS: &str = r"
+    INSERT INTO regions (id, name, placeholder)
+    SELECT DISTINCT region_id, 'Region ' || region_id, true FROM orders_import
+    ON CONFLICT (id) DO NOTHING
+";
+
+impl Staged for Order {
+    const STAGING: &'static str = r"
+        CREATE TEMP TABLE orders_import (
+            id INT,
+            region_id INT,
+            gift_name VARCHAR(50),
+            quantity INT,
+            created_at TIMESTAMPTZ
+        ) ON COMMIT DROP
+    ";
+    const COPY: &'static str =
+        "COPY orders_import (id, region_id, gift_name, quantity, created_at) FROM STDIN WITH (FORMAT csv)";
+
+    fn write(&self, writer: &mut csv::Writer<Vec<u8>>) -> csv::Result<()> {
+        writer.serialize((self.id, self.region_id, &self.gift_name, self.quantity, self.created_at))
+    }
+
+    fn merge(on_conflict: OnConflict) -> String {
+        let conflict = match on_conflict {
+            OnConflict::Reject => "",
+            OnConflict::Skip => "ON CONFLICT (id) DO NOTHING",
+            OnConflict::Upsert => r"
+                ON CONFLICT (id) DO UPDATE
+                    SET region_id = EXCLUDED.region_id,
+                        gift_name = EXCLUDED.gift_name,
+                        quantity = EXCLUDED.quantity
+            ",
+        };
+        format!(r"
+            INSERT INTO orders (id, region_id, gift_name, quantity, created_at)
+            SELECT id, region_id, gift_name, quantity, COALESCE(created_at, now())
+            FROM orders_import
+            {conflict}
+            RETURNING (xmax = 0) AS inserted
+        ")
+    }
+}
+
+impl Staged for Region {
+    const STAGING: &'static str = r"
+        CREATE TEMP TABLE regions_import (
+            id INT,
+            name VARCHAR(50),
+            parent_id INT
+        ) ON COMMIT DROP
+    ";
+    const COPY: &'static str = "COPY regions_import (id, name, parent_id) FROM STDIN WITH (FORMAT csv)";
+
+    fn write(&self, writer: &mut csv::Writer<Vec<u8>>) -> csv::Result<()> {
+        writer.serialize((self.id, &self.name, self.parent_id))
+    }
+
+    fn merge(on_conflict: OnConflict) -> String {
+        let conflict = match on_conflict {
+            OnConflict::Reject => "",
+            OnConflict::Skip => "ON CONFLICT (id) DO NOTHING",
+            OnConflict::Upsert => r"
+                ON CONFLICT (id) DO UPDATE
+                    SET name = EXCLUDED.name,
+                        parent_id = EXCLUDED.parent_id,
+                        placeholder = false
+            ",
+        };
+        format!(r"
+            INSERT INTO regions (id, name, parent_id)
+            SELECT id, name, parent_id
+            FROM regions_import
+            {conflict}
+            RETURNING (xmax = 0) AS inserted
+        ")
+    }
+}
+
+/// `before_merge` runs on the staged rows right before they are merged.
+async fn import<T: Staged>(
+    pool: &PgPool,
+    mut batches: mpsc::Receiver<Vec<T>>,
+    on_conflict: OnConflict,
+    before_merge: Option<&str>,
+    proceed: oneshot::Receiver<bool>,
+) -> Result<Option<(usize, usize)>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(T::STAGING)
+        .execute(&mut *tx)
+        .await?;
+
+    let mut copy = tx.copy_in_raw(T::COPY).await?;
+    while let Some(batch) = batches.recv().await {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for row in &batch {
+            row.write(&mut writer).map_err(|e| sqlx::Error::Io(e.into()))?;
+        }
+        copy.send(writer.into_inner().map_err(|e| sqlx::Error::Io(e.into_error()))?).await?;
+    }
+    copy.finish().await?;
+
+    // Dropping the transaction discards the staged rows.
+    if !proceed.await.unwrap_or(false) {
+        return Ok(None);
+    }
+
+    if let Some(sql) = before_merge {
+        sqlx::query(sql)
+            .execute(&mut *tx)
+            .await?;
+    }
+    let rows = sqlx::query(&T::merge(on_conflict))
+        .fetch_all(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    let inserted = rows.iter().filter(|row| row.get::<bool, _>("inserted")).count();
+    Ok(Some((inserted, rows.len() - inserted)))
+}
+
+/// Stream the result of `query` straight out of Postgres with `COPY`.
+///
+/// The `COPY` stream borrows its connection, so a task owns both and forwards
+/// the chunks; it starts before returning so a failing query is still reported.
+async fn copy_out(pool: &PgPool, query: &str) -> Result<CsvChunks, sqlx::Error> {
+    let statement = format!("COPY ({query}) TO STDOUT WITH (FORMAT csv, HEADER)");
+    let pool = pool.clone();
+    let (started, start) = oneshot::channel();
+    let (sender, receiver) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        let mut conn = match pool.acquire().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                let _ = started.send(Err(e));
+                return;
+            }
+        };
+        let mut stream = match conn.copy_out_raw(&statement).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = started.send(Err(e));
+                return;
+            }
+        };
+        let _ = started.send(Ok(()));
+        // Keep draining after the client is gone so the connection goes back
+        // to the pool in a usable state.
+        while let Some(chunk) = stream.next().await {
+            let _ = sender.send(chunk.map(Vec::from)).await;
+        }
+    });
+
+    start.await.map_err(|e| sqlx::Error::Protocol(e.to_string()))??;
+
+    Ok(Box::pin(futures::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    })))
+}
+
+#[async_trait]
+impl OrderStore for PgStore {
+    async fn select(&self, number: i32) -> Result<i32, sqlx::Error> {
+        sqlx::query_scalar!(r#"SELECT $1::INT AS "number!""#, number)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn reset(&self, include_regions: bool, fixture: Option<Fixture>) -> Result<ResetSummary, sqlx::Error> {
+        reset(&self.pool, include_regions, fixture).await
+    }
+
+    async fn insert_orders(
+        &self,
+        orders: &[Order],
+        on_conflict: OnConflict,
+        missing: MissingRegions,
+    ) -> Result<InsertSummary, sqlx::Error> {
+        insert_orders(&self.pool, orders, on_conflict, missing).await
+    }
+
+    async fn insert_regions(&self, regions: &[Region], on_conflict: OnConflict) -> Result<InsertSummary, sqlx::Error> {
+        insert_regions(&self.pool, regions, on_conflict).await
+    }
+
+    async fn import_orders(
+        &self,
+        batches: mpsc::Receiver<Vec<Order>>,
+        on_conflict: OnConflict,
+        missing: MissingRegions,
+        proceed: oneshot::Receiver<bool>,
+    ) -> Result<Option<(usize, usize)>, sqlx::Error> {
+        let placeholders = (missing == MissingRegions::Create).then_some(STAGED_PLACEHOLDERS);
+        import(&self.pool, batches, on_conflict, placeholders, proceed).await
+    }
+
+    async fn import_regions(
+        &self,
+        batches: mpsc::Receiver<Vec<Region>>,
+        on_conflict: OnConflict,
+        proceed: oneshot::Receiver<bool>,
+    ) -> Result<Option<(usize, usize)>, sqlx::Error> {
+        import(&self.pool, batches, on_conflict, None, proceed).await
+    }
+
+    async fn total_quantity(&self) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(r#"SELECT COALESCE(SUM(quantity), 0) AS "total!" from orders"#)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn popular_gift(&self) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar!("SELECT gift_name from orders GROUP BY gift_name ORDER BY SUM(quantity) DESC LIMIT 1")
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn order_stats(
+        &self,
+        filter: &OrderFilter,
+        percentiles: &[f64],
+        group_by: Option<GroupBy>,
+        top: Option<i64>,
+    ) -> Result<OrderStats, sqlx::Error> {
+        order_stats(&self.pool, filter, percentiles, group_by, top).await
+    }
+
+    async fn order_series(
+        &self,
+        filter: &OrderFilter,
+        interval: Interval,
+        tz: Tz,
+    ) -> Result<Vec<SeriesPoint>, sqlx::Error> {
+        order_series(&self.pool, filter, interval, tz.name()).await
+    }
+
+    async fn list_orders(&self) -> Result<Vec<Order>, sqlx::Error> {
+        list_orders(&self.pool).await
+    }
+
+    async fn list_regions(&self) -> Result<Vec<Region>, sqlx::Error> {
+        list_regions(&self.pool).await
+    }
+
+    async fn get_region(&self, id: i32) -> Result<Option<Region>, sqlx::Error> {
+        get_region(&self.pool, id).await
+    }
+
+    async fn update_region(&self, id: i32, update: &RegionUpdate) -> Result<Option<Region>, sqlx::Error> {
+        update_region(&self.pool, id, update).await
+    }
+
+    async fn delete_region(&self, id: i32) -> Result<RegionDeletion, sqlx::Error> {
+        delete_region(&self.pool, id).await
+    }
+
+    async fn region_totals(&self, depth: Option<i32>) -> Result<Vec<RegionTotal>, sqlx::Error> {
+        region_totals(&self.pool, depth).await
+    }
+
+    async fn top_gifts(&self, query: &TopGiftsQuery) -> Result<Vec<RegionTopGifts>, sqlx::Error> {
+        top_gifts(&self.pool, query).await
+    }
+
+    async fn orders_csv(&self) -> Result<CsvChunks, sqlx::Error> {
+        // `to_json` gives RFC 3339 timestamps, which import accepts again.
+        copy_out(
+            &self.pool,
+            "SELECT id, region_id, gift_name, quantity, to_json(created_at) #>> '{}' AS created_at FROM orders ORDER BY id",
+        ).await
+    }
+
+    async fn regions_csv(&self) -> Result<CsvChunks, sqlx::Error> {
+        copy_out(&self.pool, "SELECT id, name, parent_id FROM regions ORDER BY id").await
+    }
+}
@@ -0,0 +1,672 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use sqlx::error::{DatabaseError, ErrorKind};
+use sqlx::query::Query;
+use sqlx::sqlite::{Sqlite, SqliteArguments, SqliteConnectOptions, SqliteConnection, SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use tokio::sync::{mpsc, oneshot};
+use crate::days::orders::{
+    dedup_by_id, group_top_gifts, Fixture, GroupBy, InsertSummary, Interval, MissingRegions, OnConflict, Order,
+    OrderFilter, OrderGroup, OrderStats, OrderStore, RankedGift, Region, RegionDeletion, RegionTopGifts, RegionTotal,
+    RegionUpdate, ResetSummary, SeriesPoint, TopGiftsQuery,
+};
+
+/// `OrderFilter` as a `WHERE` condition on `?1` to `?6`, bound by `filtered`.
+const FILTER: &str = r"
+    (?1 IS NULL OR gift_name = ?1)
+    AND (?2 IS NULL OR region_id = ?2)
+    AND (?3 IS NULL OR quantity >= ?3)
+    AND (?4 IS NULL OR quantity <= ?4)
+    AND (?5 IS NULL OR created_at >= ?5)
+    AND (?6 IS NULL OR created_at < ?6)
+";
+
+/// Pairs every region with itself and its descendants down to `?depth` levels.
+const TREE: &str = r"
+    WITH RECURSIVE tree (ancestor, id, level) AS (
+        SELECT id, id, 0 FROM regions
+        UNION ALL
+        SELECT t.ancestor, r.id, t.level + 1
+        FROM tree t
+            JOIN regions r
+                ON r.parent_id = t.id
+        WHERE ?1 IS NULL OR t.level < ?1
+    )
+";
+
+/// SQLite storage in a single database file, for running without Postgres.
+///
+/// Queries are built at runtime since the query macros check against the
+/// Postgres schema. Statistics Postgres computes in SQL (percentiles, time
+/// zone buckets) are computed here from the matching rows.
+#[derive(Clone)]
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    /// Open (creating it if needed) and migrate the database at `url`, e.g.
+    /// `sqlite://orders.db` or `sqlite::memory:`.
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        // Every connection to an in-memory database gets its own empty database.
+        let max_connections = if url.contains(":memory:") { 1 } else { 10 };
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_with(options)
+            .await?;
+
+        sqlx::migrate!("./migrations-sqlite")
+            .run(&pool)
+            .await
+            .map_err(|e| sqlx::Error::Migrate(Box::new(e)))?;
+
+        Ok(Self { pool })
+    }
+}
+
+/// The format `created_at` is stored in.
+fn timestamp(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn filtered<'q>(sql: &'q str, filter: &OrderFilter) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    sqlx::query(sql)
+        .bind(filter.gift.clone())
+        .bind(filter.region_id)
+        .bind(filter.min_quantity)
+        .bind(filter.max_quantity)
+        .bind(filter.from.map(timestamp))
+        .bind(filter.to.map(timestamp))
+}
+
+/// Linear interpolation between the closest ranks, like Postgres `percentile_cont`.
+fn percentile_cont(sorted: &[i32], fraction: f64) -> Option<f64> {
+    let last = sorted.len().checked_sub(1)?;
+    let position = fraction * last as f64;
+    let (lower, upper) = (position.floor() as usize, position.ceil() as usize);
+    let weight = position - lower as f64;
+    Some(f64::from(sorted[lower]) + f64::from(sorted[upper] - sorted[lower]) * weight)
+}
+
+/// Start of the hour or day containing `at`, in local time of `tz`.
+fn bucket_start(at: DateTime<Utc>, interval: Interval, tz: Tz) -> DateTime<Utc> {
+    let local = at.with_timezone(&tz);
+    let hour = match interval {
+        Interval::Hour => local.hour(),
+        Interval::Day => 0,
+    };
+    local.date_naive()
+        .and_hms_opt(hour, 0, 0)
+        .and_then(|start| tz.from_local_datetime(&start).earliest())
+        .map_or(at, |start| start.with_timezone(&Utc))
+}
+
+/// Raised when a write would make a region its own ancestor. Reported as a
+/// check violation, like the trigger in the Postgres schema.
+#[derive(Debug)]
+struct RegionCycle(String);
+
+impl std::fmt::Display for RegionCycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RegionCycle {}
+
+impl DatabaseError for RegionCycle {
+    fn message(&self) -> &str {
+        &self.0
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        None
+    }
+
+    fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::CheckViolation
+    }
+}
+
+async fn check_acyclic(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    let looping = sqlx::query_scalar::<_, i32>(
+        r"
+            WITH RECURSIVE walk (id, ancestor) AS (
+                SELECT id, parent_id FROM regions WHERE parent_id IS NOT NULL
+                UNION
+                SELECT walk.id, regions.parent_id
+                FROM walk
+                    JOIN regions ON regions.id = walk.ancestor
+                WHERE regions.parent_id IS NOT NULL
+            )
+            SELECT id FROM walk WHERE ancestor = id LIMIT 1
+        ",
+    )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    match looping {
+        Some(id) => Err(sqlx::Error::Database(Box::new(RegionCycle(format!("region {id} is its own ancestor"))))),
+        None => Ok(()),
+    }
+}
+
+/// Rows are written one statement at a time, so foreign keys are only checked
+/// at commit to allow children before their parents within a batch.
+async fn defer_foreign_keys(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    sqlx::query("PRAGMA defer_foreign_keys = ON")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Placeholder regions for the ids `orders` refer to that do not exist yet,
+/// see `MissingRegions::Create`.
+async fn create_regions(conn: &mut SqliteConnection, orders: &[Order]) -> Result<(), sqlx::Error> {
+    let ids: BTreeSet<i32> = orders.iter().map(|o| o.region_id).collect();
+    for id in ids {
+        sqlx::query(
+            "INSERT INTO regions (id, name, placeholder) VALUES (?1, 'Region ' || ?1, 1) ON CONFLICT (id) DO NOTHING",
+        )
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn insert_orders_in(
+    conn: &mut SqliteConnection,
+    orders: &[Order],
+    on_conflict: OnConflict,
+) -> Result<InsertSummary, sqlx::Error> {
+    let rows = match on_conflict {
+        OnConflict::Upsert => dedup_by_id(orders, |o| o.id),
+        _ => orders.to_vec(),
+    };
+    let insert = match on_conflict {
+        OnConflict::Skip => r"
+            INSERT INTO orders (id, region_id, gift_name, quantity, created_at)
+            VALUES (?1, ?2, ?3, ?4, COALESCE(?5, strftime('%Y-%m-%dT%H:%M:%fZ', 'now')))
+            ON CONFLICT (id) DO NOTHING
+        ",
+        _ => r"
+            INSERT INTO orders (id, region_id, gift_name, quantity, created_at)
+            VALUES (?1, ?2, ?3, ?4, COALESCE(?5, strftime('%Y-%m-%dT%H:%M:%fZ', 'now')))
+        ",
+    };
+
+    let (mut inserted, mut updated) = (0, 0);
+    for order in &rows {
+        if on_conflict == OnConflict::Upsert {
+            let result = sqlx::query("UPDATE orders SET region_id = ?2, gift_name = ?3, quantity = ?4 WHERE id = ?1")
+                .bind(order.id)
+                .bind(order.region_id)
+                .bind(&order.gift_name)
+                .bind(order.quantity)
+                .execute(&mut *conn)
+                .await?;
+            if result.rows_affected() > 0 {
+                updated += 1;
+                continue;
+            }
+        }
+
+        let result = sqlx::query(insert)
+            .bind(order.id)
+            .bind(order.region_id)
+            .bind(&order.gift_name)
+            .bind(order.quantity)
+            .bind(order.created_at.map(timestamp))
+            .execute(&mut *conn)
+            .await?;
+        inserted += result.rows_affected() as usize;
+    }
+
+    Ok(InsertSummary {
+        received: orders.len(),
+        inserted,
+        updated,
+        skipped: orders.len() - inserted - updated,
+    })
+}
+
+async fn insert_regions_in(
+    conn: &mut SqliteConnection,
+    regions: &[Region],
+    on_conflict: OnConflict,
+) -> Result<InsertSummary, sqlx::Error> {
+    let rows = match on_conflict {
+        OnConflict::Upsert => dedup_by_id(regions, |r| r.id),
+        _ => regions.to_vec(),
+    };
+    let insert = match on_conflict {
+        OnConflict::Skip => "INSERT INTO regions (id, name, parent_id) VALUES (?1, ?2, ?3) ON CONFLICT (id) DO NOTHING",
+        _ => "INSERT INTO regions (id, name, parent_id) VALUES (?1, ?2, ?3)",
+    };
+
+    let (mut inserted, mut updated) = (0, 0);
+    for region in &rows {
+        if on_conflict == OnConflict::Upsert {
+            let result = sqlx::query("UPDATE regions SET name = ?2, parent_id = ?3, placeholder = 0 WHERE id = ?1")
+                .bind(region.id)
+                .bind(&region.name)
+                .bind(region.parent_id)
+                .execute(&mut *conn)
+                .await?;
+            if result.rows_affected() > 0 {
+                updated += 1;
+                continue;
+            }
+        }
+
+        let result = sqlx::query(insert)
+            .bind(region.id)
+            .bind(&region.name)
+            .bind(region.parent_id)
+            .execute(&mut *conn)
+            .await?;
+        inserted += result.rows_affected() as usize;
+    }
+    check_acyclic(conn).await?;
+
+    Ok(InsertSummary {
+        received: regions.len(),
+        inserted,
+        updated,
+        skipped: regions.len() - inserted - updated,
+    })
+}
+
+#[async_trait]
+impl OrderStore for SqliteStore {
+    async fn select(&self, number: i32) -> Result<i32, sqlx::Error> {
+        sqlx::query_scalar("SELECT ?1")
+            .bind(number)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn reset(&self, include_regions: bool, fixture: Option<Fixture>) -> Result<ResetSummary, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        defer_foreign_keys(&mut tx).await?;
+
+        sqlx::query("DELETE FROM orders")
+            .execute(&mut *tx)
+            .await?;
+        if include_regions {
+            sqlx::query("DELETE FROM regions")
+                .execute(&mut *tx)
+                .await?;
+        } else {
+            sqlx::query(r"
+                DELETE FROM regions
+                WHERE placeholder
+                    AND id NOT IN (SELECT parent_id FROM regions WHERE parent_id IS NOT NULL)
+            ")
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        let mut summary = ResetSummary::default();
+        if let Some(fixture) = fixture {
+            // Kept regions may already hold the fixture's, e.g. on a second Day 13 reset.
+            let regions = if include_regions { OnConflict::Reject } else { OnConflict::Skip };
+            summary.regions = insert_regions_in(&mut tx, &fixture.regions, regions).await?;
+            summary.orders = insert_orders_in(&mut tx, &fixture.orders, OnConflict::Reject).await?;
+        }
+
+        tx.commit().await?;
+        Ok(summary)
+    }
+
+    async fn insert_orders(
+        &self,
+        orders: &[Order],
+        on_conflict: OnConflict,
+        missing: MissingRegions,
+    ) -> Result<InsertSummary, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        if missing == MissingRegions::Create {
+            create_regions(&mut tx, orders).await?;
+        }
+        let summary = insert_orders_in(&mut tx, orders, on_conflict).await?;
+        tx.commit().await?;
+        Ok(summary)
+    }
+
+    async fn insert_regions(&self, regions: &[Region], on_conflict: OnConflict) -> Result<InsertSummary, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        defer_foreign_keys(&mut tx).await?;
+        let summary = insert_regions_in(&mut tx, regions, on_conflict).await?;
+        tx.commit().await?;
+        Ok(summary)
+    }
+
+    async fn import_orders(
+        &self,
+        mut batches: mpsc::Receiver<Vec<Order>>,
+        on_conflict: OnConflict,
+        missing: MissingRegions,
+        proceed: oneshot::Receiver<bool>,
+    ) -> Result<Option<(usize, usize)>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let (mut inserted, mut updated) = (0, 0);
+        while let Some(batch) = batches.recv().await {
+            if missing == MissingRegions::Create {
+                create_regions(&mut tx, &batch).await?;
+            }
+            let summary = insert_orders_in(&mut tx, &batch, on_conflict).await?;
+            inserted += summary.inserted;
+            updated += summary.updated;
+        }
+
+        if !proceed.await.unwrap_or(false) {
+            return Ok(None);
+        }
+        tx.commit().await?;
+        Ok(Some((inserted, updated)))
+    }
+
+    async fn import_regions(
+        &self,
+        mut batches: mpsc::Receiver<Vec<Region>>,
+        on_conflict: OnConflict,
+        proceed: oneshot::Receiver<bool>,
+    ) -> Result<Option<(usize, usize)>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        defer_foreign_keys(&mut tx).await?;
+        let (mut inserted, mut updated) = (0, 0);
+        while let Some(batch) = batches.recv().await {
+            let summary = insert_regions_in(&mut tx, &batch, on_conflict).await?;
+            inserted += summary.inserted;
+            updated += summary.updated;
+        }
+
+        if !proceed.await.unwrap_or(false) {
+            return Ok(None);
+        }
+        tx.commit().await?;
+        Ok(Some((inserted, updated)))
+    }
+
+    async fn total_quantity(&self) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar("SELECT COALESCE(SUM(quantity), 0) FROM orders")
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    async fn popular_gift(&self) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT gift_name FROM orders GROUP BY gift_name ORDER BY SUM(quantity) DESC LIMIT 1")
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn order_stats(
+        &self,
+        filter: &OrderFilter,
+        percentiles: &[f64],
+        group_by: Option<GroupBy>,
+        top: Option<i64>,
+    ) -> Result<OrderStats, sqlx::Error> {
+        let sql = format!(r"
+            SELECT COUNT(*) AS count,
+                   COALESCE(SUM(quantity), 0) AS total,
+                   AVG(quantity) AS average,
+                   MIN(quantity) AS min,
+                   MAX(quantity) AS max
+            FROM orders
+            WHERE {FILTER}
+        ");
+        let summary = filtered(&sql, filter)
+            .fetch_one(&self.pool)
+            .await?;
+
+        let mut quantities = vec![];
+        if !percentiles.is_empty() {
+            let sql = format!("SELECT quantity FROM orders WHERE {FILTER} ORDER BY quantity");
+            for row in filtered(&sql, filter).fetch_all(&self.pool).await? {
+                quantities.push(row.try_get::<i32, _>("quantity")?);
+            }
+        }
+        let percentiles = percentiles.iter()
+            .map(|p| (format!("p{p}"), percentile_cont(&quantities, p / 100.0)))
+            .collect();
+
+        // A negative limit means no limit in SQLite.
+        let limit = top.unwrap_or(-1);
+        let groups = match group_by {
+            None => None,
+            Some(GroupBy::Gift) => {
+                let sql = format!(r"
+                    SELECT gift_name,
+                           COUNT(*) AS count,
+                           SUM(quantity) AS total,
+                           AVG(quantity) AS average
+                    FROM orders
+                    WHERE {FILTER}
+                    GROUP BY gift_name
+                    ORDER BY total DESC, gift_name
+                    LIMIT ?7
+                ");
+                let mut groups = vec![];
+                for row in filtered(&sql, filter).bind(limit).fetch_all(&self.pool).await? {
+                    groups.push(OrderGroup {
+                        gift: Some(row.try_get("gift_name")?),
+                        region_id: None,
+                        region: None,
+                        count: row.try_get("count")?,
+                        total: row.try_get("total")?,
+                        average: row.try_get("average")?,
+                    });
+                }
+                Some(groups)
+            }
+            Some(GroupBy::Region) => {
+                let sql = format!(r"
+                    SELECT orders.region_id,
+                           regions.name,
+                           COUNT(*) AS count,
+                           SUM(quantity) AS total,
+                           AVG(quantity) AS average
+                    FROM orders
+                    LEFT JOIN regions ON regions.id = orders.region_id
+                    WHERE {FILTER}
+                    GROUP BY orders.region_id, regions.name
+                    ORDER BY total DESC, orders.region_id
+                    LIMIT ?7
+                ");
+                let mut groups = vec![];
+                for row in filtered(&sql, filter).bind(limit).fetch_all(&self.pool).await? {
+                    groups.push(OrderGroup {
+                        gift: None,
+                        region_id: Some(row.try_get("region_id")?),
+                        region: Some(row.try_get("name")?),
+                        count: row.try_get("count")?,
+                        total: row.try_get("total")?,
+                        average: row.try_get("average")?,
+                    });
+                }
+                Some(groups)
+            }
+        };
+
+        Ok(OrderStats {
+            count: summary.try_get("count")?,
+            total: summary.try_get("total")?,
+            average: summary.try_get("average")?,
+            min: summary.try_get("min")?,
+            max: summary.try_get("max")?,
+            percentiles,
+            groups,
+        })
+    }
+
+    async fn order_series(
+        &self,
+        filter: &OrderFilter,
+        interval: Interval,
+        tz: Tz,
+    ) -> Result<Vec<SeriesPoint>, sqlx::Error> {
+        let sql = format!("SELECT created_at, quantity FROM orders WHERE {FILTER}");
+        let mut buckets = BTreeMap::<DateTime<Utc>, (i64, i64)>::new();
+        for row in filtered(&sql, filter).fetch_all(&self.pool).await? {
+            let created_at = row.try_get::<DateTime<Utc>, _>("created_at")?;
+            let quantity = row.try_get::<i64, _>("quantity")?;
+            let (count, total) = buckets.entry(bucket_start(created_at, interval, tz)).or_default();
+            *count += 1;
+            *total += quantity;
+        }
+
+        Ok(buckets.into_iter()
+            .map(|(bucket, (count, total))| SeriesPoint { bucket, count, total })
+            .collect())
+    }
+
+    async fn list_orders(&self) -> Result<Vec<Order>, sqlx::Error> {
+        sqlx::query_as::<_, Order>("SELECT id, region_id, gift_name, quantity, created_at FROM orders ORDER BY id")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn list_regions(&self) -> Result<Vec<Region>, sqlx::Error> {
+        sqlx::query_as::<_, Region>("SELECT id, name, parent_id FROM regions ORDER BY id")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn get_region(&self, id: i32) -> Result<Option<Region>, sqlx::Error> {
+        sqlx::query_as::<_, Region>("SELECT id, name, parent_id FROM regions WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn update_region(&self, id: i32, update: &RegionUpdate) -> Result<Option<Region>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let region = sqlx::query_as::<_, Region>(
+            "UPDATE regions SET name = ?2, parent_id = ?3, placeholder = 0 WHERE id = ?1 RETURNING id, name, parent_id",
+        )
+            .bind(id)
+            .bind(&update.name)
+            .bind(update.parent_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        check_acyclic(&mut tx).await?;
+        tx.commit().await?;
+        Ok(region)
+    }
+
+    async fn delete_region(&self, id: i32) -> Result<RegionDeletion, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let has_orders: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM orders WHERE region_id = ?1)")
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if has_orders {
+            return Ok(RegionDeletion::HasOrders);
+        }
+
+        let result = sqlx::query("DELETE FROM regions WHERE id = ?1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(if result.rows_affected() > 0 { RegionDeletion::Deleted } else { RegionDeletion::NotFound })
+    }
+
+    async fn region_totals(&self, depth: Option<i32>) -> Result<Vec<RegionTotal>, sqlx::Error> {
+        let sql = format!(r"
+            {TREE}
+            SELECT a.name AS region,
+                sum(o.quantity) AS total
+            FROM tree t
+                JOIN regions a
+                    ON a.id = t.ancestor
+                JOIN orders o
+                    ON o.region_id = t.id
+            GROUP BY a.id, a.name
+            ORDER BY a.name ASC
+        ");
+        sqlx::query_as::<_, RegionTotal>(&sql)
+            .bind(depth)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn top_gifts(&self, query: &TopGiftsQuery) -> Result<Vec<RegionTopGifts>, sqlx::Error> {
+        // No LATERAL joins or arrays here: rank the gifts per region with a
+        // window function and pass the region filter as a JSON array.
+        let sql = format!(r"
+            {TREE},
+            gift_totals AS (
+                SELECT t.ancestor AS region_id,
+                    o.gift_name,
+                    {aggregate} AS total
+                FROM tree t
+                    JOIN orders o
+                        ON o.region_id = t.id
+                GROUP BY t.ancestor, o.gift_name
+            ),
+            region_totals AS (
+                SELECT region_id,
+                    sum(total) AS total
+                FROM gift_totals
+                GROUP BY region_id
+            ),
+            ranked AS (
+                SELECT region_id,
+                    gift_name,
+                    total,
+                    {rank} AS rank
+                FROM gift_totals
+            )
+            SELECT r.id AS region_id,
+                r.name AS region,
+                COALESCE(rt.total, 0) AS region_total,
+                k.gift_name,
+                k.rank,
+                k.total
+            FROM regions r
+                LEFT JOIN region_totals rt
+                    ON rt.region_id = r.id
+                LEFT JOIN ranked k
+                    ON k.region_id = r.id
+                    AND k.rank <= ?2
+            WHERE ?3 IS NULL OR r.id IN (SELECT value FROM json_each(?3))
+            ORDER BY r.name ASC, r.id, k.rank, k.gift_name
+        ",
+            aggregate = query.metric.aggregate(),
+            rank = query.ranking.window(),
+        );
+        let regions = query.regions.as_ref().map(|regions| {
+            let ids = regions.iter().map(i32::to_string).collect::<Vec<_>>();
+            format!("[{}]", ids.join(","))
+        });
+        let rows = sqlx::query_as::<_, RankedGift>(&sql)
+            .bind(query.depth)
+            .bind(query.limit)
+            .bind(regions)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(group_top_gifts(rows))
+    }
+}
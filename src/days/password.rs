@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use axum::http::StatusCode;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::days::breach;
+use crate::days::sequence::{Automaton, Pattern};
+
+/// Built-in policies, by name.
+const PRESETS: [(&str, &str); 2] = [
+    ("game", include_str!("../../policies/game.toml")),
+    ("strong", include_str!("../../policies/strong.toml")),
+];
+
+/// Ordered password rules; the first failing one decides the verdict.
+///
+/// Policies are plain data, written in TOML for the presets under `policies/`
+/// or sent as JSON, e.g.
+/// `{"rules": [{"type": "length", "min": 8, "reason": "8 chars", "status": 400}]}`.
+#[derive(Deserialize, Debug)]
+pub struct Policy {
+    /// Reason given when every rule passes.
+    #[serde(default = "default_success")]
+    pub success: String,
+    pub rules: Vec<Rule>,
+}
+
+fn default_success() -> String {
+    "that's a nice password".to_string()
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Rule {
+    #[serde(flatten)]
+    pub check: Check,
+    pub reason: String,
+    /// Response status when the rule fails.
+    #[serde(default = "default_status", deserialize_with = "status_code")]
+    pub status: StatusCode,
+}
+
+fn default_status() -> StatusCode {
+    StatusCode::BAD_REQUEST
+}
+
+fn status_code<'de, D: Deserializer<'de>>(deserializer: D) -> Result<StatusCode, D::Error> {
+    let code = u16::deserialize(deserializer)?;
+    StatusCode::from_u16(code).map_err(serde::de::Error::custom)
+}
+
+fn regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+
+fn sequence<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Automaton, D::Error> {
+    Pattern::deserialize(deserializer)?
+        .compile()
+        .map_err(serde::de::Error::custom)
+}
+
+fn yes() -> bool {
+    true
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LengthUnit {
+    #[default]
+    Chars,
+    /// UTF-8 bytes.
+    Bytes,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CharClass {
+    Uppercase,
+    Lowercase,
+    /// Any numeric character, not only ASCII digits.
+    Digit,
+    /// Anything but letters, digits and whitespace.
+    Symbol,
+}
+
+impl CharClass {
+    fn contains(self, c: char) -> bool {
+        match self {
+            CharClass::Uppercase => c.is_uppercase(),
+            CharClass::Lowercase => c.is_lowercase(),
+            CharClass::Digit => c.is_numeric(),
+            CharClass::Symbol => !c.is_alphanumeric() && !c.is_whitespace(),
+        }
+    }
+}
+
+/// What a rule tests, selected by its `type`.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Check {
+    Length {
+        #[serde(default)]
+        min: usize,
+        max: Option<usize>,
+        #[serde(default)]
+        unit: LengthUnit,
+    },
+    /// At least `min` of the classes occur, all of them when `min` is absent.
+    CharClasses {
+        classes: Vec<CharClass>,
+        min: Option<usize>,
+    },
+    /// Number of numeric characters.
+    DigitCount {
+        #[serde(default)]
+        min: usize,
+        max: Option<usize>,
+    },
+    /// The numbers formed by runs of ASCII digits add up to `equals`.
+    DigitSum {
+        equals: u64,
+    },
+    /// None of the substrings occur.
+    Forbidden {
+        substrings: Vec<String>,
+        #[serde(default)]
+        ignore_case: bool,
+    },
+    /// The input matches `pattern`, or must not when `matches` is false.
+    Regex {
+        #[serde(deserialize_with = "regex")]
+        pattern: Regex,
+        #[serde(default = "yes")]
+        matches: bool,
+    },
+    /// Some character falls in one of the inclusive ranges.
+    UnicodeRange {
+        ranges: Vec<(char, char)>,
+    },
+    /// Some character is an emoji.
+    Emoji,
+    /// The hex SHA-256 of the input ends with `suffix`.
+    HashSuffix {
+        suffix: String,
+    },
+    /// The input matches a sequence pattern, see `sequence::Pattern`.
+    Sequence {
+        #[serde(deserialize_with = "sequence")]
+        pattern: Automaton,
+    },
+    /// Not on the bundled list of common and breached passwords.
+    NotCommon,
+    /// Not a dictionary word with decorations, like `Sunshine123!`.
+    NotDictionaryWord,
+    /// No run of `min_length` neighbouring keys, like `qwer` or `4321`.
+    NoKeyboardWalk {
+        #[serde(default = "default_walk_length")]
+        min_length: usize,
+    },
+}
+
+fn default_walk_length() -> usize {
+    4
+}
+
+impl Check {
+    pub fn passes(&self, input: &str) -> bool {
+        match self {
+            Check::Length { min, max, unit } => {
+                let len = match unit {
+                    LengthUnit::Chars => input.chars().count(),
+                    LengthUnit::Bytes => input.len(),
+                };
+                len >= *min && max.is_none_or(|max| len <= max)
+            }
+            Check::CharClasses { classes, min } => {
+                let present = classes.iter()
+                    .filter(|class| input.chars().any(|c| class.contains(c)))
+                    .count();
+                present >= min.unwrap_or(classes.len())
+            }
+            Check::DigitCount { min, max } => {
+                let digits = input.chars().filter(|c| c.is_numeric()).count();
+                digits >= *min && max.is_none_or(|max| digits <= max)
+            }
+            Check::DigitSum { equals } => digit_sum(input) == Some(*equals),
+            Check::Forbidden { substrings, ignore_case } => {
+                let input = if *ignore_case { input.to_lowercase() } else { input.to_string() };
+                substrings.iter().all(|substring| {
+                    let substring = if *ignore_case { substring.to_lowercase() } else { substring.clone() };
+                    !input.contains(&substring)
+                })
+            }
+            Check::Regex { pattern, matches } => pattern.is_match(input) == *matches,
+            Check::UnicodeRange { ranges } => input.chars()
+                .any(|c| ranges.iter().any(|(from, to)| (*from..=*to).contains(&c))),
+            Check::Emoji => input.chars().any(|c| emojis::get(c.to_string().as_str()).is_some()),
+            Check::HashSuffix { suffix } => sha256::digest(input).ends_with(suffix.as_str()),
+            Check::Sequence { pattern } => pattern.matches(input),
+            Check::NotCommon => !breach::corpus().is_common(input),
+            Check::NotDictionaryWord => !breach::corpus().is_dictionary_word(input),
+            Check::NoKeyboardWalk { min_length } => breach::keyboard_walk(input, *min_length).is_none(),
+        }
+    }
+}
+
+/// Sum of the numbers in `input`, `None` if it overflows.
+fn digit_sum(input: &str) -> Option<u64> {
+    input.split(|c: char| !c.is_ascii_digit())
+        .filter(|number| !number.is_empty())
+        .try_fold(0u64, |sum, number| sum.checked_add(number.parse().ok()?))
+}
+
+impl Policy {
+    /// The first rule `input` breaks, if any.
+    pub fn first_failure(&self, input: &str) -> Option<&Rule> {
+        self.rules.iter().find(|rule| !rule.check.passes(input))
+    }
+}
+
+/// Guess rate of an offline attack on a fast hash, used for crack times.
+const GUESSES_PER_SECOND: f64 = 1e10;
+
+/// Every rule's outcome, for showing a checklist while the password is typed.
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub nice: bool,
+    /// Failed rules in policy order.
+    pub failures: Vec<Failure>,
+    /// Outcome of each rule, keyed by its reason.
+    pub rules: BTreeMap<String, bool>,
+    pub strength: Strength,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Failure {
+    pub reason: String,
+    pub status: u16,
+}
+
+/// Estimate based on the character sets used, treat it as an upper bound.
+/// Common passwords and decorated dictionary words only count as one guess
+/// out of the bundled lists.
+#[derive(Serialize, Debug)]
+pub struct Strength {
+    pub entropy_bits: f64,
+    /// 0 (trivial) to 4 (very strong).
+    pub score: u8,
+    /// Expected time to find the password, trying half of the search space.
+    pub crack_time_seconds: f64,
+    pub crack_time: String,
+}
+
+impl Strength {
+    pub fn estimate(input: &str) -> Self {
+        let classes = [
+            (CharClass::Lowercase, 26.0),
+            (CharClass::Uppercase, 26.0),
+            (CharClass::Digit, 10.0),
+            (CharClass::Symbol, 33.0),
+        ];
+        let mut pool = classes.iter()
+            .filter(|(class, _)| input.chars().any(|c| class.contains(c)))
+            .map(|(_, size)| size)
+            .sum::<f64>();
+        if !input.is_ascii() {
+            pool += 100.0;
+        }
+
+        // A character repeating the previous one adds next to nothing.
+        let mut entropy_bits = 0.0;
+        let mut previous = None;
+        for c in input.chars() {
+            entropy_bits += if Some(c) == previous { 1.0 } else { pool.max(1.0).log2() };
+            previous = Some(c);
+        }
+
+        let corpus = breach::corpus();
+        if corpus.is_common(input) || corpus.is_dictionary_word(input) {
+            entropy_bits = entropy_bits.min((corpus.size as f64).log2());
+        }
+
+        let score = match entropy_bits {
+            bits if bits < 28.0 => 0,
+            bits if bits < 36.0 => 1,
+            bits if bits < 60.0 => 2,
+            bits if bits < 128.0 => 3,
+            _ => 4,
+        };
+        let crack_time_seconds = if entropy_bits == 0.0 {
+            0.0
+        } else {
+            (entropy_bits - 1.0).exp2() / GUESSES_PER_SECOND
+        };
+
+        Self {
+            entropy_bits: (entropy_bits * 100.0).round() / 100.0,
+            score,
+            crack_time_seconds,
+            crack_time: describe_duration(crack_time_seconds),
+        }
+    }
+}
+
+fn describe_duration(seconds: f64) -> String {
+    const UNITS: [(&str, f64); 5] = [
+        ("year", 365.25 * 86400.0),
+        ("day", 86400.0),
+        ("hour", 3600.0),
+        ("minute", 60.0),
+        ("second", 1.0),
+    ];
+    if seconds < 1.0 {
+        return "instant".to_string();
+    }
+    if seconds >= 100.0 * UNITS[0].1 {
+        return "centuries".to_string();
+    }
+    let (unit, size) = UNITS.iter()
+        .find(|(_, size)| seconds >= *size)
+        .unwrap_or(&UNITS[4]);
+    let count = (seconds / size).floor();
+    format!("{count} {unit}{}", if count == 1.0 { "" } else { "s" })
+}
+
+impl Policy {
+    /// Evaluate every rule instead of stopping at the first failure.
+    pub fn report(&self, input: &str) -> Report {
+        let mut failures = vec![];
+        let mut rules = BTreeMap::new();
+        for rule in &self.rules {
+            let passed = rule.check.passes(input);
+            if !passed {
+                failures.push(Failure { reason: rule.reason.clone(), status: rule.status.as_u16() });
+            }
+            // A reason shared by several rules only passes if all of them do.
+            *rules.entry(rule.reason.clone()).or_insert(true) &= passed;
+        }
+
+        Report {
+            nice: failures.is_empty(),
+            failures,
+            rules,
+            strength: Strength::estimate(input),
+        }
+    }
+}
+
+/// Parse the built-in policies. They ship with the binary, so a broken one is a bug.
+pub fn presets() -> BTreeMap<String, Policy> {
+    PRESETS.iter()
+        .map(|(name, toml)| {
+            let policy = toml::from_str(toml).unwrap_or_else(|e| panic!("Invalid preset {name}: {e}"));
+            (name.to_string(), policy)
+        })
+        .collect()
+}
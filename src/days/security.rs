@@ -0,0 +1,52 @@
+use axum::extract::Request;
+use axum::http::{header, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use base64::{engine::general_purpose, Engine as _};
+
+/// CSP of HTML responses that do not set their own. `{nonce}` is replaced by
+/// the response's nonce; style attributes stay allowed for the email layout.
+const HTML_POLICY: &str = "default-src 'none'; script-src 'nonce-{nonce}'; style-src 'nonce-{nonce}'; \
+    style-src-attr 'unsafe-inline'; img-src 'self' https:; base-uri 'none'; form-action 'none'; \
+    frame-ancestors 'none'";
+
+/// CSP for raw user HTML: a unique origin with scripts, forms and plugins disabled.
+pub const SANDBOX_POLICY: &str = "sandbox";
+
+/// Random value for `nonce` attributes, new for every request.
+#[derive(Clone, Debug)]
+pub struct CspNonce(pub String);
+
+impl CspNonce {
+    fn generate() -> Self {
+        Self(general_purpose::STANDARD.encode(rand::random::<[u8; 16]>()))
+    }
+}
+
+/// Protective headers for every response, plus CSP and frame options for
+/// HTML. Handlers get the nonce as an `Extension<CspNonce>` and may set their
+/// own `Content-Security-Policy`, which is kept.
+pub async fn security_headers(mut request: Request, next: Next) -> Response {
+    let nonce = CspNonce::generate();
+    request.extensions_mut().insert(nonce.clone());
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(header::REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+
+    let is_html = headers.get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/html"));
+    if is_html {
+        headers.insert(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+        if !headers.contains_key(header::CONTENT_SECURITY_POLICY) {
+            // Base64 only uses characters valid in a header.
+            let policy = HTML_POLICY.replace("{nonce}", &nonce.0);
+            if let Ok(policy) = HeaderValue::from_str(&policy) {
+                headers.insert(header::CONTENT_SECURITY_POLICY, policy);
+            }
+        }
+    }
+    response
+}
@@ -0,0 +1,344 @@
+use std::collections::{BTreeSet, HashMap};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+/// Most letters, or pairs, a single pattern may list. Automata grow with them.
+const MAX_LETTERS: usize = 256;
+
+/// Declarative rule over the order of characters in a string.
+///
+/// Patterns are compiled into an `Automaton` that reads the input once, e.g.
+/// "j, o and y in that order and in no other order" is
+/// `{"type": "ordered", "letters": "joy", "exactly_once": true}`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Pattern {
+    /// The letters occur in this order, with anything in between. With
+    /// `exactly_once` these letters occur nowhere else, so filtering the input
+    /// down to them leaves exactly `letters`.
+    Ordered {
+        #[serde(deserialize_with = "letters")]
+        letters: String,
+        #[serde(default)]
+        exactly_once: bool,
+    },
+    /// Each of the letters occurs exactly once, in any order.
+    ExactlyOnce {
+        #[serde(deserialize_with = "letters")]
+        letters: String,
+    },
+    /// A letter, any letter, then the first letter again, like "xyx". With
+    /// `distinct` the middle letter must differ, so "xxx" does not count.
+    Sandwich {
+        #[serde(default)]
+        distinct: bool,
+    },
+    /// None of the two-character sequences occur next to each other.
+    ForbiddenAdjacent {
+        #[serde(deserialize_with = "pairs")]
+        pairs: Vec<String>,
+    },
+    All {
+        patterns: Vec<Pattern>,
+    },
+    Any {
+        patterns: Vec<Pattern>,
+    },
+    Not {
+        pattern: Box<Pattern>,
+    },
+}
+
+fn letters<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let letters = String::deserialize(deserializer)?;
+    if letters.chars().count() > MAX_LETTERS {
+        return Err(D::Error::custom(format!("at most {MAX_LETTERS} letters are allowed")));
+    }
+    Ok(letters)
+}
+
+fn pairs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let pairs = Vec::<String>::deserialize(deserializer)?;
+    if pairs.len() > MAX_LETTERS {
+        return Err(D::Error::custom(format!("at most {MAX_LETTERS} pairs are allowed")));
+    }
+    Ok(pairs)
+}
+
+impl Pattern {
+    pub fn compile(&self) -> Result<Automaton, String> {
+        Ok(match self {
+            Pattern::Ordered { letters, exactly_once } => {
+                let letters = letters.chars().collect::<Vec<_>>();
+                if letters.is_empty() {
+                    return Err("ordered needs at least one letter".to_string());
+                }
+                Automaton::Dfa(Dfa::ordered(&letters, *exactly_once))
+            }
+            Pattern::ExactlyOnce { letters } => {
+                let letters = letters.chars().collect::<BTreeSet<_>>();
+                Automaton::All(letters.into_iter().map(|letter| Automaton::Dfa(Dfa::once(letter))).collect())
+            }
+            Pattern::Sandwich { distinct } => Automaton::Sandwich { distinct: *distinct },
+            Pattern::ForbiddenAdjacent { pairs } => {
+                let pairs = pairs.iter()
+                    .map(|pair| match pair.chars().collect::<Vec<_>>()[..] {
+                        [first, second] => Ok((first, second)),
+                        _ => Err(format!("{pair:?} is not a pair of characters")),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Automaton::Dfa(Dfa::forbidden_adjacent(&pairs))
+            }
+            Pattern::All { patterns } => Automaton::All(compile_all(patterns)?),
+            Pattern::Any { patterns } => Automaton::Any(compile_all(patterns)?),
+            Pattern::Not { pattern } => Automaton::Not(Box::new(pattern.compile()?)),
+        })
+    }
+}
+
+fn compile_all(patterns: &[Pattern]) -> Result<Vec<Automaton>, String> {
+    patterns.iter().map(Pattern::compile).collect()
+}
+
+/// Deterministic automaton over an open alphabet. A character takes the
+/// state's own transition if it has one, then the transition `shared` by all
+/// states that follow it, then the state's `otherwise` edge. Sharing keeps
+/// the tables linear in the pattern size. State 0 is the start.
+#[derive(Clone, Debug)]
+pub struct Dfa {
+    states: Vec<DfaState>,
+    shared: HashMap<char, usize>,
+}
+
+#[derive(Clone, Debug)]
+struct DfaState {
+    on: HashMap<char, usize>,
+    /// Whether the `shared` transitions apply; false for dead states.
+    shared: bool,
+    otherwise: usize,
+    accepting: bool,
+}
+
+impl DfaState {
+    fn new(otherwise: usize, accepting: bool) -> Self {
+        Self { on: HashMap::new(), shared: true, otherwise, accepting }
+    }
+
+    /// Stays put whatever it reads.
+    fn dead(this: usize) -> Self {
+        Self { shared: false, ..Self::new(this, false) }
+    }
+}
+
+impl Dfa {
+    /// States `0..=n` count the letters seen so far; `n + 1` is the dead
+    /// state entered when `exactly_once` sees one of the letters out of turn.
+    fn ordered(letters: &[char], exactly_once: bool) -> Self {
+        let n = letters.len();
+        let dead = n + 1;
+        let mut states = (0..=n)
+            .map(|i| DfaState::new(i, i == n))
+            .chain([DfaState::dead(dead)])
+            .collect::<Vec<_>>();
+        for (i, next) in letters.iter().enumerate() {
+            states[i].on.insert(*next, i + 1);
+        }
+        let shared = if exactly_once {
+            letters.iter().map(|letter| (*letter, dead)).collect()
+        } else {
+            HashMap::new()
+        };
+        Self { states, shared }
+    }
+
+    /// Counts `letter` up to two: accepting at one, stuck at two.
+    fn once(letter: char) -> Self {
+        let mut states = vec![DfaState::new(0, false), DfaState::new(1, true), DfaState::new(2, false)];
+        states[0].on.insert(letter, 1);
+        states[1].on.insert(letter, 2);
+        Self { states, shared: HashMap::new() }
+    }
+
+    /// State 0 follows characters that start no pair, state `k + 1` follows the
+    /// k-th distinct first character, and the last state is dead.
+    fn forbidden_adjacent(pairs: &[(char, char)]) -> Self {
+        let firsts = pairs.iter().map(|(first, _)| *first).collect::<BTreeSet<_>>();
+        let shared = firsts.iter()
+            .enumerate()
+            .map(|(k, first)| (*first, k + 1))
+            .collect::<HashMap<_, _>>();
+        let dead = firsts.len() + 1;
+
+        let mut states = (0..dead)
+            .map(|_| DfaState::new(0, true))
+            .chain([DfaState::dead(dead)])
+            .collect::<Vec<_>>();
+        for (first, second) in pairs {
+            states[shared[first]].on.insert(*second, dead);
+        }
+        Self { states, shared }
+    }
+
+    fn step(&self, state: usize, c: char) -> usize {
+        let state = &self.states[state];
+        state.on.get(&c)
+            .or_else(|| state.shared.then(|| self.shared.get(&c)).flatten())
+            .copied()
+            .unwrap_or(state.otherwise)
+    }
+}
+
+/// A compiled `Pattern`. Everything is a `Dfa` except the sandwich, whose
+/// letters are only known from the input, so it keeps the last two letters.
+#[derive(Clone, Debug)]
+pub enum Automaton {
+    Dfa(Dfa),
+    Sandwich { distinct: bool },
+    All(Vec<Automaton>),
+    Any(Vec<Automaton>),
+    Not(Box<Automaton>),
+}
+
+#[derive(Clone, Debug)]
+enum State {
+    Dfa(usize),
+    Sandwich { window: [Option<char>; 2], found: bool },
+    Product(Vec<State>),
+}
+
+impl Automaton {
+    pub fn matches(&self, input: &str) -> bool {
+        let state = input.chars().fold(self.start(), |state, c| self.step(state, c));
+        self.accepts(&state)
+    }
+
+    fn start(&self) -> State {
+        match self {
+            Automaton::Dfa(_) => State::Dfa(0),
+            Automaton::Sandwich { .. } => State::Sandwich { window: [None; 2], found: false },
+            Automaton::All(automata) | Automaton::Any(automata) =>
+                State::Product(automata.iter().map(Automaton::start).collect()),
+            Automaton::Not(automaton) => automaton.start(),
+        }
+    }
+
+    fn step(&self, state: State, c: char) -> State {
+        match (self, state) {
+            (Automaton::Dfa(dfa), State::Dfa(state)) => State::Dfa(dfa.step(state, c)),
+            (Automaton::Sandwich { distinct }, State::Sandwich { window: [first, middle], found }) => {
+                let found = found || (c.is_alphabetic()
+                    && first == Some(c)
+                    && middle.is_some_and(|middle| middle.is_alphabetic() && !(*distinct && middle == c)));
+                State::Sandwich { window: [middle, Some(c)], found }
+            }
+            (Automaton::All(automata) | Automaton::Any(automata), State::Product(states)) => State::Product(
+                automata.iter().zip(states).map(|(automaton, state)| automaton.step(state, c)).collect()
+            ),
+            (Automaton::Not(automaton), state) => automaton.step(state, c),
+            (_, state) => state,
+        }
+    }
+
+    fn accepts(&self, state: &State) -> bool {
+        match (self, state) {
+            (Automaton::Dfa(dfa), State::Dfa(state)) => dfa.states[*state].accepting,
+            (Automaton::Sandwich { .. }, State::Sandwich { found, .. }) => *found,
+            (Automaton::All(automata), State::Product(states)) =>
+                automata.iter().zip(states).all(|(automaton, state)| automaton.accepts(state)),
+            (Automaton::Any(automata), State::Product(states)) =>
+                automata.iter().zip(states).any(|(automaton, state)| automaton.accepts(state)),
+            (Automaton::Not(automaton), state) => !automaton.accepts(state),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(json: &str) -> Automaton {
+        serde_json::from_str::<Pattern>(json).unwrap().compile().unwrap()
+    }
+
+    /// Every string over `alphabet` up to `max_len` characters.
+    fn strings(alphabet: &str, max_len: usize) -> Vec<String> {
+        let mut all = vec![String::new()];
+        let mut last = all.clone();
+        for _ in 0..max_len {
+            last = last.iter()
+                .flat_map(|prefix| alphabet.chars().map(move |c| format!("{prefix}{c}")))
+                .collect();
+            all.extend(last.iter().cloned());
+        }
+        all
+    }
+
+    fn check(automaton: &Automaton, alphabet: &str, reference: impl Fn(&str) -> bool) {
+        for input in strings(alphabet, 6) {
+            assert_eq!(automaton.matches(&input), reference(&input), "{input:?}");
+        }
+    }
+
+    fn is_subsequence(letters: &str, input: &str) -> bool {
+        let mut chars = input.chars();
+        letters.chars().all(|letter| chars.any(|c| c == letter))
+    }
+
+    #[test]
+    fn joy() {
+        let joy = compile(r#"{"type": "ordered", "letters": "joy", "exactly_once": true}"#);
+        check(&joy, "joyx", |input| input.chars().filter(|c| "joy".contains(*c)).collect::<String>() == "joy");
+    }
+
+    #[test]
+    fn ordered() {
+        let joy = compile(r#"{"type": "ordered", "letters": "joy"}"#);
+        check(&joy, "joyx", |input| is_subsequence("joy", input));
+        let repeated = compile(r#"{"type": "ordered", "letters": "aab", "exactly_once": true}"#);
+        check(&repeated, "abx", |input| input.chars().filter(|c| "ab".contains(*c)).collect::<String>() == "aab");
+    }
+
+    #[test]
+    fn sandwich() {
+        let reference = |distinct: bool| move |input: &str| {
+            input.chars().collect::<Vec<_>>().windows(3).any(|w| {
+                w[0] == w[2] && w[0].is_alphabetic() && w[1].is_alphabetic() && !(distinct && w[0] == w[1])
+            })
+        };
+        check(&compile(r#"{"type": "sandwich"}"#), "ab1", reference(false));
+        check(&compile(r#"{"type": "sandwich", "distinct": true}"#), "ab1", reference(true));
+    }
+
+    #[test]
+    fn forbidden_adjacent() {
+        let pairs = ["ab", "ba", "aa", "cd"];
+        let automaton = compile(r#"{"type": "forbidden_adjacent", "pairs": ["ab", "ba", "aa", "cd"]}"#);
+        check(&automaton, "abcd", |input| {
+            let chars = input.chars().collect::<Vec<_>>();
+            !chars.windows(2).any(|w| pairs.contains(&format!("{}{}", w[0], w[1]).as_str()))
+        });
+    }
+
+    #[test]
+    fn combinators() {
+        let automaton = compile(r#"{"type": "all", "patterns": [
+            {"type": "exactly_once", "letters": "ab"},
+            {"type": "not", "pattern": {"type": "any", "patterns": [
+                {"type": "sandwich"}, {"type": "ordered", "letters": "ba"}
+            ]}}
+        ]}"#);
+        check(&automaton, "abc", |input| {
+            let count = |letter| input.chars().filter(|c| *c == letter).count();
+            let sandwich = input.chars().collect::<Vec<_>>().windows(3).any(|w| w[0] == w[2]);
+            count('a') == 1 && count('b') == 1 && !sandwich && !is_subsequence("ba", input)
+        });
+    }
+
+    #[test]
+    fn too_many_letters() {
+        let letters = "x".repeat(MAX_LETTERS + 1);
+        let json = format!(r#"{{"type": "ordered", "letters": "{letters}"}}"#);
+        assert!(serde_json::from_str::<Pattern>(&json).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A named stopwatch. Only absolute UTC instants are stored, so elapsed time
+/// stays correct across midnight and restarts.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Timer {
+    pub name: String,
+    pub started_at: DateTime<Utc>,
+    pub paused_at: Option<DateTime<Utc>>,
+    pub stopped_at: Option<DateTime<Utc>>,
+    /// Total time spent paused before the current pause, in milliseconds.
+    pub paused_ms: i64,
+    pub laps: Vec<Lap>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Lap {
+    pub at: DateTime<Utc>,
+    /// Running time when the lap was taken, in milliseconds.
+    pub elapsed_ms: i64,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimerState {
+    Running,
+    Paused,
+    Stopped,
+}
+
+impl Timer {
+    pub fn start(name: String, now: DateTime<Utc>) -> Self {
+        Self {
+            name,
+            started_at: now,
+            paused_at: None,
+            stopped_at: None,
+            paused_ms: 0,
+            laps: vec![],
+        }
+    }
+
+    pub fn state(&self) -> TimerState {
+        if self.stopped_at.is_some() {
+            TimerState::Stopped
+        } else if self.paused_at.is_some() {
+            TimerState::Paused
+        } else {
+            TimerState::Running
+        }
+    }
+
+    /// Running time at `at`, excluding pauses.
+    pub fn elapsed_at(&self, at: DateTime<Utc>) -> Duration {
+        let end = self.stopped_at.unwrap_or(at);
+        let end = self.paused_at.map_or(end, |paused| paused.min(end));
+        end - self.started_at - Duration::milliseconds(self.paused_ms)
+    }
+
+    pub fn lap(&mut self, now: DateTime<Utc>) -> Result<(), String> {
+        self.expect_state(TimerState::Running, "lap")?;
+        let elapsed_ms = self.elapsed_at(now).num_milliseconds();
+        self.laps.push(Lap { at: now, elapsed_ms });
+        Ok(())
+    }
+
+    pub fn pause(&mut self, now: DateTime<Utc>) -> Result<(), String> {
+        self.expect_state(TimerState::Running, "pause")?;
+        self.paused_at = Some(now);
+        Ok(())
+    }
+
+    pub fn resume(&mut self, now: DateTime<Utc>) -> Result<(), String> {
+        self.expect_state(TimerState::Paused, "resume")?;
+        if let Some(paused_at) = self.paused_at.take() {
+            self.paused_ms += (now - paused_at).num_milliseconds();
+        }
+        Ok(())
+    }
+
+    pub fn stop(&mut self, now: DateTime<Utc>) -> Result<(), String> {
+        if self.state() == TimerState::Stopped {
+            return Err(format!("Timer {} is already stopped", self.name));
+        }
+        if let Some(paused_at) = self.paused_at.take() {
+            self.paused_ms += (now - paused_at).num_milliseconds();
+        }
+        self.stopped_at = Some(now);
+        Ok(())
+    }
+
+    fn expect_state(&self, expected: TimerState, action: &str) -> Result<(), String> {
+        let state = self.state();
+        if state != expected {
+            let state = format!("{state:?}").to_lowercase();
+            return Err(format!("Cannot {action} timer {}: it is {state}", self.name));
+        }
+        Ok(())
+    }
+
+    pub fn view(&self, now: DateTime<Utc>) -> TimerView {
+        let elapsed = self.elapsed_at(now);
+        let mut previous = Duration::zero();
+        let laps = self.laps.iter()
+            .map(|lap| {
+                let elapsed = Duration::milliseconds(lap.elapsed_ms);
+                let split = elapsed - previous;
+                previous = elapsed;
+                LapView {
+                    at: lap.at,
+                    elapsed_seconds: seconds(elapsed),
+                    elapsed: elapsed.to_string(),
+                    split_seconds: seconds(split),
+                    split: split.to_string(),
+                }
+            })
+            .collect();
+
+        TimerView {
+            name: self.name.clone(),
+            state: self.state(),
+            started_at: self.started_at,
+            stopped_at: self.stopped_at,
+            elapsed_seconds: seconds(elapsed),
+            elapsed: elapsed.to_string(),
+            laps,
+        }
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.num_milliseconds() as f64 / 1000.0
+}
+
+#[derive(Serialize, Debug)]
+pub struct LapView {
+    at: DateTime<Utc>,
+    elapsed_seconds: f64,
+    /// ISO-8601 duration, e.g. `PT12.345S`.
+    elapsed: String,
+    split_seconds: f64,
+    split: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TimerView {
+    name: String,
+    state: TimerState,
+    started_at: DateTime<Utc>,
+    stopped_at: Option<DateTime<Utc>>,
+    elapsed_seconds: f64,
+    /// ISO-8601 duration, e.g. `PT12.345S`.
+    elapsed: String,
+    laps: Vec<LapView>,
+}
@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use uuid::Uuid;
+
+/// Largest timestamp a ULID can hold (48 bits of milliseconds).
+const MAX_TIMESTAMP_MS: u64 = (1 << 48) - 1;
+
+/// Representations a ULID can be converted from and to.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Repr {
+    /// Detect the representation from the input's shape.
+    #[default]
+    Auto,
+    Ulid,
+    Uuid,
+    /// Unix milliseconds or RFC 3339; converts to the smallest ULID of that millisecond.
+    Timestamp,
+    /// Raw 128-bit value, in decimal or `0x`-prefixed hex.
+    U128,
+}
+
+/// Every representation of one ULID, or why the input could not be read.
+#[derive(Serialize, Debug)]
+pub struct Conversion {
+    pub input: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<Repr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ulid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datetime: Option<DateTime<Utc>>,
+    /// Decimal string, since JSON numbers cannot hold 128 bits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub u128: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Conversion {
+    pub fn new(input: &str, from: Repr) -> Self {
+        match parse(input, from) {
+            Ok((from, ulid)) => Self {
+                from: Some(from),
+                ..Self::from_ulid(input, ulid)
+            },
+            Err(error) => Self {
+                input: input.to_string(),
+                from: None,
+                ulid: None,
+                uuid: None,
+                timestamp_ms: None,
+                datetime: None,
+                u128: None,
+                hex: None,
+                error: Some(error),
+            },
+        }
+    }
+
+    pub fn from_ulid(input: &str, ulid: Ulid) -> Self {
+        let value = u128::from(ulid);
+        Self {
+            input: input.to_string(),
+            from: None,
+            ulid: Some(ulid.to_string()),
+            uuid: Some(Uuid::from(ulid).to_string()),
+            timestamp_ms: Some(ulid.timestamp_ms()),
+            datetime: Some(ulid.datetime().into()),
+            u128: Some(value.to_string()),
+            hex: Some(format!("0x{value:032x}")),
+            error: None,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+pub fn parse(input: &str, from: Repr) -> Result<(Repr, Ulid), String> {
+    let input = input.trim();
+    let repr = match from {
+        Repr::Auto => detect(input),
+        from => from,
+    };
+
+    let ulid = match repr {
+        Repr::Auto => return Err("unrecognized representation".to_string()),
+        Repr::Ulid => Ulid::from_string(input).map_err(|e| format!("invalid ULID: {e}"))?,
+        Repr::Uuid => Uuid::parse_str(input)
+            .map(Ulid::from)
+            .map_err(|e| format!("invalid UUID: {e}"))?,
+        Repr::Timestamp => Ulid::from_parts(parse_timestamp(input)?, 0),
+        Repr::U128 => {
+            let value = match input.strip_prefix("0x").or_else(|| input.strip_prefix("0X")) {
+                Some(hex) => u128::from_str_radix(hex, 16),
+                None => input.parse::<u128>(),
+            };
+            Ulid::from(value.map_err(|e| format!("invalid 128-bit integer: {e}"))?)
+        }
+    };
+    Ok((repr, ulid))
+}
+
+/// Unix milliseconds or an RFC 3339 date-time.
+pub fn parse_timestamp(input: &str) -> Result<u64, String> {
+    let ms = match input.parse::<u64>() {
+        Ok(ms) => ms,
+        Err(_) => {
+            let datetime = DateTime::parse_from_rfc3339(input)
+                .map_err(|e| format!("invalid timestamp: {e}"))?;
+            u64::try_from(datetime.timestamp_millis())
+                .map_err(|_| "timestamp before the Unix epoch".to_string())?
+        }
+    };
+    if ms > MAX_TIMESTAMP_MS {
+        return Err(format!("timestamp {ms} does not fit in 48 bits"));
+    }
+    Ok(ms)
+}
+
+pub fn to_system_time(timestamp_ms: u64) -> SystemTime {
+    UNIX_EPOCH + std::time::Duration::from_millis(timestamp_ms)
+}
+
+fn detect(input: &str) -> Repr {
+    let is_decimal = !input.is_empty() && input.bytes().all(|b| b.is_ascii_digit());
+    if input.len() == 26 && !input.contains('-') {
+        Repr::Ulid
+    } else if input.len() == 36 || (input.len() == 32 && input.bytes().all(|b| b.is_ascii_hexdigit())) {
+        Repr::Uuid
+    } else if input.starts_with("0x") || input.starts_with("0X") {
+        Repr::U128
+    } else if is_decimal && input.parse::<u64>().is_ok_and(|ms| ms <= MAX_TIMESTAMP_MS) {
+        Repr::Timestamp
+    } else if is_decimal {
+        Repr::U128
+    } else if DateTime::parse_from_rfc3339(input).is_ok() {
+        Repr::Timestamp
+    } else {
+        Repr::Auto
+    }
+}
+
+/// A named date test evaluated on ULID timestamps in the requested time zone.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DatePredicate {
+    /// Any of the listed `[month, day]` pairs, e.g. `[[12, 24]]` for Christmas Eve.
+    Dates {
+        name: String,
+        dates: Vec<(u32, u32)>,
+    },
+    /// Inclusive range of calendar dates.
+    Range {
+        name: String,
+        from: NaiveDate,
+        to: NaiveDate,
+    },
+    /// Any of the listed weekdays, 0 being Monday.
+    Weekdays {
+        name: String,
+        weekdays: Vec<u32>,
+    },
+    /// Any of the listed hours of the day, 0-23.
+    Hours {
+        name: String,
+        hours: Vec<u32>,
+    },
+}
+
+impl DatePredicate {
+    pub fn name(&self) -> &str {
+        match self {
+            DatePredicate::Dates { name, .. }
+            | DatePredicate::Range { name, .. }
+            | DatePredicate::Weekdays { name, .. }
+            | DatePredicate::Hours { name, .. } => name,
+        }
+    }
+
+    pub fn matches<T: TimeZone>(&self, time: &DateTime<T>) -> bool {
+        match self {
+            DatePredicate::Dates { dates, .. } => dates.contains(&(time.month(), time.day())),
+            DatePredicate::Range { from, to, .. } => (*from..=*to).contains(&time.date_naive()),
+            DatePredicate::Weekdays { weekdays, .. } =>
+                weekdays.contains(&time.weekday().num_days_from_monday()),
+            DatePredicate::Hours { hours, .. } => hours.contains(&time.hour()),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Day,
+    /// ISO weeks, labelled by their Monday.
+    Week,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HistogramBin {
+    pub bucket: NaiveDate,
+    pub count: u32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Analytics {
+    pub tz: String,
+    pub total: usize,
+    pub invalid: Vec<Conversion>,
+    pub predicates: BTreeMap<String, u32>,
+    #[serde(rename = "in the future")]
+    pub in_the_future: u32,
+    #[serde(rename = "LSB is 1")]
+    pub lsb_is_1: u32,
+    /// Count per local hour of day, index 0 being midnight.
+    pub hours: Vec<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub histogram: Option<Vec<HistogramBin>>,
+}
+
+pub fn parse_tz(tz: &str) -> Result<Tz, String> {
+    tz.parse::<Tz>().map_err(|_| format!("unknown time zone {tz}"))
+}
+
+pub fn analyze(
+    inputs: &[String],
+    tz: Tz,
+    predicates: &[DatePredicate],
+    histogram: Option<Bucket>,
+    now: DateTime<Utc>,
+) -> Analytics {
+    let mut invalid = vec![];
+    let mut counts = vec![0u32; predicates.len()];
+    let mut in_the_future = 0;
+    let mut lsb_is_1 = 0;
+    let mut hours = vec![0u32; 24];
+    let mut bins = BTreeMap::<NaiveDate, u32>::new();
+
+    for input in inputs {
+        let ulid = match parse(input, Repr::Ulid) {
+            Ok((_, ulid)) => ulid,
+            Err(_) => {
+                invalid.push(Conversion::new(input, Repr::Ulid));
+                continue;
+            }
+        };
+        let utc: DateTime<Utc> = ulid.datetime().into();
+        let local = utc.with_timezone(&tz);
+
+        for (count, predicate) in counts.iter_mut().zip(predicates) {
+            if predicate.matches(&local) {
+                *count += 1;
+            }
+        }
+        if utc > now {
+            in_the_future += 1;
+        }
+        if ulid.random() & 1 == 1 {
+            lsb_is_1 += 1;
+        }
+        hours[local.hour() as usize] += 1;
+
+        if let Some(bucket) = histogram {
+            let date = local.date_naive();
+            let key = match bucket {
+                Bucket::Day => date,
+                Bucket::Week => date - Duration::days(date.weekday().num_days_from_monday().into()),
+            };
+            *bins.entry(key).or_default() += 1;
+        }
+    }
+
+    let mut named = BTreeMap::new();
+    for (predicate, count) in predicates.iter().zip(counts) {
+        *named.entry(predicate.name().to_string()).or_default() += count;
+    }
+
+    Analytics {
+        tz: tz.name().to_string(),
+        total: inputs.len() - invalid.len(),
+        invalid,
+        predicates: named,
+        in_the_future,
+        lsb_is_1,
+        hours,
+        histogram: histogram.map(|_| bins.into_iter()
+            .map(|(bucket, count)| HistogramBin { bucket, count })
+            .collect()),
+    }
+}